@@ -17,50 +17,67 @@ use iron_archivist::*;
 struct SimpleRenderer;
 
 impl Renderer for SimpleRenderer {
-    fn render_dir(&self, path_str: &str, entries: &[Entry])
+    fn render_dir(&self, context: &RenderContext, entries: &[Entry])
             -> RenderResult {
         let mut result = format!(
             "<h1>/{}</h1><ul><li><a href=\"..\">..</a>",
-            path_str
+            escape_html(&context.path)
         );
         for e in entries.iter() {
             result.push_str(&format!(
-                "<li><a href=\"{}{}\">{}</a></li>",
-                &e.file_name,
-                // A path to a directory must end with an ending slash
-                if e.is_dir { "/" } else { "" },
-                &e.file_name,
+                "<li><a href=\"{}\">{}</a></li>",
+                // The url is already encoded, and ends with a slash for directories
+                // But the name must be escaped, as it may contain markup
+                &e.href,
+                e.escaped_display_name(),
             ));
         }
         result.push_str("</ul>");
         Ok(result)
     }
 
-    fn render_verbatim(&self, path_str: &str, content: &str) -> RenderResult {
+    fn render_verbatim(&self, context: &RenderContext, content: &str) -> RenderResult {
         Ok(format!(
             "<h1>{}</h1><a href=\".\">Back</a><pre>{}</pre>",
-            path_str,
-            content
+            escape_html(&context.path),
+            escape_html(content)
         ))
     }
 
-    fn render_markdown(&self, path_str: &str, content: &str) -> RenderResult {
+    fn render_verbatim_lines(&self, context: &RenderContext, lines: &[&str]) -> RenderResult {
+        let mut result = format!(
+            "<h1>{}</h1><a href=\".\">Back</a><pre>",
+            escape_html(&context.path)
+        );
+        for (n, line) in lines.iter().enumerate() {
+            // Give each line an anchor, so that `#L42' jumps to line 42
+            result.push_str(&format!(
+                "<span id=\"L{0}\"><a href=\"#L{0}\">{0}</a> {1}</span>\n",
+                n + 1,
+                escape_html(line)
+            ));
+        }
+        result.push_str("</pre>");
+        Ok(result)
+    }
+
+    fn render_markdown(&self, context: &RenderContext, content: &str) -> RenderResult {
         Ok(format!(
             "<h1>{}</h1><a href=\".\">Back</a>{}",
-            path_str,
+            escape_html(&context.path),
             content
         ))
     }
 
     fn render_error(
             &self,
-            _: &str,
+            _: &RenderContext,
             code: usize,
             message: &str) -> RenderResult {
         Ok(format!(
             "<h1>Error {}</h1><p>{}</p>",
             code,
-            message
+            escape_html(message)
         ))
     }
 }
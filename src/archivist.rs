@@ -1,36 +1,73 @@
 use url::percent_encoding::percent_decode;
 
 use iron::prelude::*;
+use iron::error::IronError;
 use iron::status;
 use iron::Url;
-use iron::headers::ContentType;
+use iron::headers::{
+    AcceptRanges, Authorization, Basic, ByteRangeSpec, CacheControl, CacheDirective,
+    ContentEncoding, ContentLength, ContentRange, ContentRangeSpec, ContentType, ETag, Encoding,
+    EntityTag, Host, Range, RangeUnit, UserAgent
+};
+use iron::method::Method;
 use iron::middleware::Handler;
+use iron::response::BodyReader;
+use iron::mime::{Attr, Mime, SubLevel, TopLevel, Value};
 use iron::modifiers::Header;
 use iron::modifiers::Redirect;
+use iron::typemap::Key;
+use flate2::Compression;
+use flate2::write::GzEncoder;
 use mount;
 use url;
-use urlencoded::UrlEncodedQuery;
+use urlencoded::{QueryMap, UrlEncodedQuery};
 
-use pulldown_cmark::{html, Parser};
-
-use std::cmp::Ordering;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::fs;
 use std::fs::*;
 use std::io;
 use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::net::IpAddr;
 use std::path::*;
-use std::sync::Arc;
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use config::*;
-use entry::*;
+use entry::Entry;
+use listing::*;
+use logging::*;
+use markdown;
+use download;
+use download::ZipRefusal;
+use walk::*;
+use feed;
+use json;
+use negotiation;
+use conditional::Validators;
+use throttle::Throttle;
+use pool::Pool;
+use lru::Lru;
 use renderer::*;
 
-/// Order in which the entries should be sorted
-#[derive(Clone, Copy, Eq, PartialEq, Debug)]
-enum EntryOrder {
-    Lexicographical,
-    Chronological,
-}
+// The number of directories, or sidecars, whose contents are remembered
+// The ones looked at least recently are forgotten first
+const MEMO_CAPACITY: usize = 4096;
+
+// The segments of the prefixes at which roots are mounted, along with their archivists
+type Mounts<T> = Vec<(Vec<String>, Archivist<T>)>;
+
+// The callback given each record of a request
+type AccessLog = Box<dyn Fn(&AccessEvent) + Send + Sync>;
+
+// The compressed bodies of responses, by the file or directory they were served from,
+// their entity tag, and their content coding
+type CompressedBodies = Mutex<Lru<(PathBuf, String, &'static str), Vec<u8>>>;
 
 /// A handler that serves static directory indices and files
 ///
@@ -40,8 +77,23 @@ enum EntryOrder {
 pub struct Archivist<T: Renderer> {
     raw: bool,
     root: PathBuf,
-    config: Config,
+    config: Arc<Config>,
     renderer: Arc<T>,
+    // The number of entries in subdirectories, along with the time they were counted at
+    child_counts: Mutex<Lru<PathBuf, (SystemTime, usize)>>,
+    // The weights read from sidecars, with the modification times of the sidecars
+    sidecar_weights: Mutex<Lru<PathBuf, (SystemTime, Option<f64>)>>,
+    // The number of requests handled, for numbering them
+    requests: AtomicUsize,
+    throttle: Option<Throttle>,
+    // The threads inspecting the entries of directories, if more than one is configured
+    stat_pool: Option<Pool>,
+    // The responses already compressed, if they are to be kept
+    compressed_bodies: Option<CompressedBodies>,
+    access_log: Option<AccessLog>,
+    // The archivists of the roots mounted at prefixes, longest prefix first
+    // The root of this archivist is not served if there are any
+    mounts: Mounts<T>,
 }
 
 impl<T> Archivist<T> where T: Renderer {
@@ -55,11 +107,64 @@ impl<T> Archivist<T> where T: Renderer {
         Archivist {
             raw: false,
             root: PathBuf::from(Path::new(&config.root_dir)),
-            config: config.clone(),
-            renderer: renderer
+            config: Arc::new(config.clone()),
+            renderer,
+            child_counts: Mutex::new(Lru::new(MEMO_CAPACITY)),
+            sidecar_weights: Mutex::new(Lru::new(MEMO_CAPACITY)),
+            requests: AtomicUsize::new(0),
+            throttle: throttle_for(config),
+            stat_pool: stat_pool_for(config),
+            compressed_bodies: compressed_bodies_for(config),
+            access_log: None,
+            mounts: Vec::new(),
         }
     }
 
+    /// Summons a `Archivist` serving several roots, each mounted at its own prefix.
+    ///
+    /// Each request is served from the root mounted at the longest prefix of its path, with the
+    /// prefix removed, as if by `mount`. Requests matching none of the prefixes are answered with
+    /// 404. The prefixes are matched against the path as it is in the url, segment by segment, so
+    /// `docs` matches `/docs/guide.md` but not `/docsets/`.
+    ///
+    /// The configuration applies to every root. Health checks, the description of the
+    /// capabilities and the rate limit are answered before the root is chosen.
+    ///
+    /// # Arguments
+    /// * `config`   - The configuration to be used, except for `root_dir`
+    /// * `roots`    - The prefixes, such as `docs`, each with the root served there
+    /// * `renderer` - A shared, thread safe pointer to the renderer.
+    ///
+    pub fn summon_multi(config: &Config, roots: Vec<(String, PathBuf)>, renderer: Arc<T>)
+            -> Archivist<T> {
+        let mut mounts : Mounts<T> = roots.into_iter()
+            .map(|(prefix, root)| {
+                let prefix = prefix.split('/')
+                    .filter(|s| !s.is_empty())
+                    .map(String::from)
+                    .collect();
+                // The rules scoped to directories are relative to the mounted root
+                let mut mount_config = config.clone();
+                mount_config.root_dir = root.to_string_lossy().into_owned();
+                // These are answered once, by the archivist holding the mounts
+                mount_config.health_path = None;
+                mount_config.capabilities_path = None;
+                mount_config.auth_user = None;
+                mount_config.auth_password = None;
+                let mut archivist = Archivist::summon(&mount_config, renderer.clone());
+                archivist.root = root;
+                archivist.throttle = None;
+                (prefix, archivist)
+            })
+            .collect();
+        mounts.sort_by_key(|m| Reverse(m.0.len()));
+        let mut archivist = Archivist::summon(config, renderer);
+        // Only the mounted archivists list directories
+        archivist.stat_pool = None;
+        archivist.mounts = mounts;
+        archivist
+    }
+
     /// Summons a `Archivist` which serves all files as-is, using a certain configuration.
     ///
     /// # Arguments
@@ -70,237 +175,2929 @@ impl<T> Archivist<T> where T: Renderer {
         Archivist {
             raw: true,
             root: PathBuf::from(Path::new(&config.root_dir)),
-            config: config.clone(),
-            renderer: renderer
+            config: Arc::new(config.clone()),
+            renderer,
+            child_counts: Mutex::new(Lru::new(MEMO_CAPACITY)),
+            sidecar_weights: Mutex::new(Lru::new(MEMO_CAPACITY)),
+            requests: AtomicUsize::new(0),
+            throttle: throttle_for(config),
+            stat_pool: stat_pool_for(config),
+            compressed_bodies: compressed_bodies_for(config),
+            access_log: None,
+            mounts: Vec::new(),
+        }
+    }
+
+    /// Sets a callback to be called after each request, with a record of the request.
+    ///
+    /// # Arguments
+    /// * `log` - The callback, which would typically write the record to an access log
+    ///
+    pub fn on_access<F>(mut self, log: F) -> Archivist<T>
+            where F: Fn(&AccessEvent) + Send + Sync + 'static {
+        self.access_log = Some(Box::new(log));
+        self
+    }
+
+    // The server is ready if the served directories can be read
+    fn health(&self) -> Response {
+        let ready = if self.mounts.is_empty() {
+            fs::read_dir(&self.root).is_ok()
+        } else {
+            self.mounts.iter().all(|(_, a)| fs::read_dir(&a.root).is_ok())
+        };
+        if ready {
+            Response::with(("OK", status::Ok, Header(ContentType::plaintext())))
+        } else {
+            Response::with((
+                "Unavailable",
+                status::ServiceUnavailable,
+                Header(ContentType::plaintext())
+            ))
         }
     }
 
     #[inline]
-    fn not_found(&self, path_str: &str) -> IronResult<Response> {
-        self.renderer.render_error(
-            &path_str,
-            404,
-            "The requested archive is not found"
-        ).map(|s| Response::with((
-            s,
-            Header(ContentType::html()),
-            status::NotFound
-        )))
+    fn not_found(&self, context: &RenderContext) -> IronResult<Response> {
+        let code = self.config.not_found_status;
+        self.error_page(
+            context,
+            code,
+            "The requested archive is not found",
+            status::Status::from_u16(code)
+        )
+    }
+
+    #[inline]
+    fn invalid_format(&self, context: &RenderContext) -> IronResult<Response> {
+        self.error_page(context, 416, "The requested file is not valid UTF8", status::NotFound)
+    }
+
+    #[inline]
+    fn uri_too_long(&self, context: &RenderContext) -> IronResult<Response> {
+        self.error_page(context, 414, "The requested path is too long", status::UriTooLong)
+    }
+
+    #[inline]
+    fn too_large(&self, context: &RenderContext) -> IronResult<Response> {
+        self.error_page(
+            context,
+            500,
+            "The requested page is too large to be sent",
+            status::InternalServerError
+        )
     }
 
     #[inline]
-    fn invalid_format(&self, path_str: &str) -> IronResult<Response> {
-        self.renderer.render_error(
-            &path_str,
+    fn blocked(&self, context: &RenderContext) -> IronResult<Response> {
+        let code = self.config.blocked_user_agent_status;
+        self.error_page(
+            context,
+            code,
+            "Access is denied to this user agent",
+            status::Status::from_u16(code)
+        )
+    }
+
+    // Asks the client to come back once the file is completely written
+    fn still_written(&self, context: &RenderContext, remaining: Duration) -> IronResult<Response> {
+        self.error_page(
+            context,
+            503,
+            "The requested file is still being written",
+            status::ServiceUnavailable
+        ).map(|mut r| {
+            set_retry_after(&mut r, remaining);
+            r
+        })
+    }
+
+    // Tells the client that none of the range it asked for can be sent
+    fn range_not_satisfiable(&self, context: &RenderContext, length: u64) -> IronResult<Response> {
+        self.error_page(
+            context,
             416,
-            "The requested file is not valid UTF8"
-        ).map(|s| Response::with((
-            s,
-            Header(ContentType::html()),
-            status::NotFound
-        )))
+            "The requested range is not satisfiable",
+            status::RangeNotSatisfiable
+        ).map(|mut r| {
+            r.headers.set(ContentRange(ContentRangeSpec::Bytes {
+                range: None,
+                instance_length: Some(length),
+            }));
+            r
+        })
     }
-}
 
-impl<T> Handler for Archivist<T> where T: Renderer + Send + Sync + 'static {
-    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+    // Asks the client to slow down
+    fn too_many_requests(&self, context: &RenderContext, wait: Duration) -> IronResult<Response> {
+        self.error_page(
+            context,
+            429,
+            "Too many requests have been made",
+            status::TooManyRequests
+        ).map(|mut r| {
+            set_retry_after(&mut r, wait);
+            r
+        })
+    }
 
-        // Construct the path to the file being accessed
-        let mut path = PathBuf::new();
-        for n in req.url.path() {
-            // The path in the url is percent encoded
-            // So it needs to be decoded here
-            path.push(String::from(percent_decode(n.as_bytes())
-                                   .decode_utf8()
-                                   .unwrap()
+    fn unauthorized(&self, context: &RenderContext) -> IronResult<Response> {
+        let realm = self.config.auth_realm.replace('\\', "\\\\").replace('"', "\\\"");
+        self.error_page(
+            context,
+            401,
+            "The archive requires logging in",
+            status::Unauthorized
+        ).map(|mut r| {
+            r.headers.set_raw(
+                "WWW-Authenticate",
+                vec![format!("Basic realm=\"{}\"", realm).into_bytes()]
+            );
+            r
+        })
+    }
+
+    #[inline]
+    fn internal_error(&self, context: &RenderContext) -> IronResult<Response> {
+        self.error_page(
+            context,
+            500,
+            "The requested archive cannot be read",
+            status::InternalServerError
+        )
+    }
+
+    // Renders the error page for a listing that cannot be built
+    //
+    // The directory may have been removed since it was looked up,
+    // or it or its entries may have become unreadable
+    fn listing_error(&self, context: &RenderContext, e: &IronError) -> IronResult<Response> {
+        if e.response.status == Some(status::NotFound) {
+            self.not_found(context)
+        } else {
+            self.internal_error(context)
+        }
+    }
+
+    #[inline]
+    fn bad_request(&self, context: &RenderContext, message: &str) -> IronResult<Response> {
+        self.error_page(context, 400, message, status::BadRequest)
+    }
+
+    // Renders the error page
+    // The error is also kept with the response, in case the client wants it as JSON instead
+    fn error_page(
+        &self,
+        context: &RenderContext,
+        code: u16,
+        message: &str,
+        status: status::Status,
+    ) -> IronResult<Response> {
+        let page = match self.error_page_file(code) {
+            Some(page) => Ok(page),
+            None => self.renderer.render_error(
+                context,
+                code as usize,
+                message
+            ).map(|s| self.with_footer(s)),
+        };
+        page.map(|s| {
+            let mut response = Response::with((
+                s,
+                Header(ContentType::html()),
+                status
             ));
+            response.extensions.insert::<ErrorDetails>(ErrorDetails {
+                code,
+                message: String::from(message),
+                path: context.path.clone(),
+            });
+            response
+        })
+    }
+
+    // Reads the file configured to be served for the status, if any
+    fn error_page_file(&self, code: u16) -> Option<String> {
+        self.config.error_pages.get(&code)
+            .and_then(|path| fs::read_to_string(self.root.join(path.trim_start_matches('/'))).ok())
+    }
+
+    // Wraps the rendered page in a response body,
+    // unless it is larger than allowed
+    fn rendered_page(
+        &self,
+        context: &RenderContext,
+        rendered: IronResult<String>,
+    ) -> IronResult<Response> {
+        let content = self.with_footer(rendered?);
+        match self.config.max_response_size {
+            Some(max) if content.len() > max => return self.too_large(context),
+            _ => (),
         }
-        let path_string = format!("{}", path.as_path().display());
+        let mut response = response_html(content);
+        if let (Some(policy), Some(nonce)) =
+                (self.config.content_security_policy.as_ref(), context.csp_nonce.as_ref()) {
+            response.headers.set_raw(
+                "Content-Security-Policy",
+                vec![policy.replace("{nonce}", nonce).into_bytes()]
+            );
+        }
+        Ok(response)
+    }
 
-        // If we are at the root and that the archivist is mounted (using iron/mount)
-        // Then make sure that there is a trailing slash
-        //
-        // First check if the Archivist is mounted
-        if let Some(url) = req.extensions.get::<mount::OriginalUrl>() {
-            // Then check if the last part of the original url
-            // differs from the one we are now handling
-            //
-            // If there is a difference
-            // it indicates that a trailing slash have been added by the mount
-            if url.path().last() != req.url.path().last() {
-                let mut original_url : url::Url = url.clone().into();
-                // Redirect to the proper url (with trailing slash)
-                original_url.path_segments_mut().unwrap().push("");
-                return Ok(Response::with((
-                    "Redirecting to root directory.",
-                    Redirect(Url::from_generic_url(original_url).unwrap()),
-                    status::MovedPermanently
-                )));
+    // Adds the configured footer to the rendered page,
+    // before the end of its body if it has one
+    fn with_footer(&self, mut content: String) -> String {
+        if let Some(ref footer) = self.config.footer {
+            let at = content.rfind("</body>").unwrap_or(content.len());
+            content.insert_str(at, footer);
+        }
+        content
+    }
+
+    // The address of the client, as given by the trusted proxy in the header,
+    // or of the connection if there is no such header
+    fn client_ip(&self, req: &Request) -> IpAddr {
+        self.config.client_ip_header.as_ref()
+            .and_then(|header| req.headers.get_raw(header))
+            .and_then(|v| v.last())
+            .and_then(|v| str::from_utf8(v).ok())
+            .and_then(|v| v.rsplit(',').next())
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(req.remote_addr.ip())
+    }
+
+    // The ID of the request, as given by the client in the header,
+    // or generated if the client did not give one
+    fn request_id(&self, req: &Request, header: &str) -> String {
+        let number = self.requests.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+        let given = req.headers.get_raw(header)
+            .and_then(|v| v.first())
+            .and_then(|v| str::from_utf8(v).ok())
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty());
+        match given {
+            Some(id) => String::from(id),
+            None => match self.config.request_id_scheme {
+                RequestIdScheme::Random => random_id(),
+                RequestIdScheme::Counter => number.to_string(),
+            },
+        }
+    }
+
+    // Replaces the body of an error response with JSON if the client prefers it
+    fn json_error(&self, req: &Request, response: &mut Response) {
+        let details = match response.extensions.remove::<ErrorDetails>() {
+            Some(details) => details,
+            None => match response.status {
+                Some(s) if s.is_client_error() || s.is_server_error() => ErrorDetails {
+                    code: s.to_u16(),
+                    message: String::from(s.canonical_reason().unwrap_or("")),
+                    path: String::new(),
+                },
+                _ => return,
+            },
+        };
+        negotiation::add_vary(response, "Accept");
+        let preferred = negotiation::preferred_type(req, &["text/html", "application/json"]);
+        if preferred != Some("application/json") {
+            return;
+        }
+        let body = json::error(details.code, &details.message, &details.path);
+        response.headers.set(ContentType::json());
+        response.set_mut(body);
+    }
+
+    /// Gathers the entries in a directory, without rendering them.
+    ///
+    /// # Arguments
+    /// * `rel_path` - The path to the directory, relative to the served root
+    /// * `query`    - The query parameters specifying how the entries are ordered
+    ///
+    /// # Error
+    /// Returns an error if the directory is not found, or if it cannot be read.
+    ///
+    pub fn build_listing<P: AsRef<Path>>(&self, rel_path: &P, query: &QueryMap)
+            -> IronResult<Listing> {
+        self.build_listing_in(rel_path, query, None)
+    }
+
+    // Gathers the entries in a directory, in the default order unless the query specifies one
+    //
+    // The default order is given along with whether or not it is descending
+    fn build_listing_in<P: AsRef<Path>>(
+        &self,
+        rel_path: &P,
+        query: &QueryMap,
+        default_order: Option<(EntryOrder, bool)>,
+    ) -> IronResult<Listing> {
+        let full_path = self.root.as_path().join(rel_path);
+        match self.config.method_for(&full_path) {
+            Ok(Some(AccessMethod::Dir)) => (),
+            _ => return Err(IronError::new(
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "The requested directory is not found"
+                ),
+                status::NotFound
+            )),
+        }
+
+        // First collect the directory entries that we can access
+        // The directory may have been removed since it was looked up
+        let dir_entries : Vec<DirEntry> = match fs::read_dir(&full_path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Err(IronError::new(
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "The requested directory is not found"
+                ),
+                status::NotFound
+            )),
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+            Ok(read_dir) => read_dir,
+        }.flatten().collect();
+        let mut gathered = gather_entries(&self.config, self.stat_pool.as_ref(), dir_entries)
+            .map_err(|e| IronError::new(e, status::InternalServerError))?;
+
+        // Then sort the entries in the order specified
+        // And keep only the page asked for
+        let (order, reversed) = match (entry_order(query, &self.config), default_order) {
+            (None, Some((order, reversed))) => (Some(order), reversed),
+            (order, _) => (order, is_order_reversed(query)),
+        };
+        let page_size = page_size_for(query, &self.config);
+        let (order, next_page, page) = match page_size {
+            Some(page_size) if self.config.pagination == Pagination::Cursor => {
+                let order = order.or(Some(EntryOrder::Lexicographical));
+                (order, page_by_cursor(
+                    &mut gathered,
+                    query,
+                    order,
+                    reversed,
+                    self.config.dirs_first,
+                    page_size,
+                    &self.config.pinned
+                ), None)
+            },
+            page_size => {
+                // Without an order asked for, the order file of the directory decides
+                // The entries it does not name follow by name
+                let curated = match order {
+                    Some(_) => None,
+                    None => read_order_file(&full_path, &self.config),
+                };
+                // Otherwise the weights in the sidecars of the entries decide, if configured
+                // The entries without weights follow by name
+                match curated {
+                    Some(names) => {
+                        sort_entries(&mut gathered, Some(EntryOrder::Lexicographical), false);
+                        pin_entries(&mut gathered, &names);
+                    },
+                    None if order.is_none() && self.config.sidecar_extension.is_some() => {
+                        sort_entries(&mut gathered, Some(EntryOrder::Lexicographical), false);
+                        sort_by_weight(&mut gathered, |e| self.sidecar_weight(&e.path()));
+                    },
+                    None => {
+                        sort_entries(&mut gathered, order, reversed);
+                        if self.config.dirs_first {
+                            group_dirs_first(&mut gathered);
+                        }
+                    },
+                }
+                pin_entries(&mut gathered, &self.config.pinned);
+                let page = page_size.map(|n| page_by_offset(&mut gathered, query, n));
+                let next_page = page
+                    .filter(|&(page, pages)| page < pages)
+                    .map(|(page, _)| (page + 1).to_string());
+                (order, next_page, page)
+            },
+        };
+        let prev_page = page
+            .filter(|&(page, _)| page > 1)
+            .map(|(page, _)| (page - 1).to_string());
+
+        // Then collect them as entry objects
+        // Entries that cannot be read, such as those removed in the meantime, are left out
+        // Unless the name is not UTF-8 and the configuration says to fail the listing then
+        let mut entries = Vec::with_capacity(gathered.len());
+        let mut skipped = 0;
+        for (de, entry) in gathered {
+            let mut entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    if self.config.non_utf8_names == NonUtf8Names::Error
+                            && de.file_name().to_str().is_none() {
+                        return Err(IronError::new(e, status::InternalServerError));
+                    }
+                    skipped += 1;
+                    continue;
+                },
+            };
+            if entry.is_dir && self.config.count_children {
+                entry.child_count = self.count_children(&de.path());
             }
+            entries.push(entry);
         }
 
-        // Construct the path to the actual file in the file system
-        let full_path = self.root.as_path().join(&path);
+        Ok(Listing {
+            entries,
+            order,
+            reversed: reversed && order.is_some(),
+            next_page,
+            prev_page,
+            page,
+            page_size,
+            skipped,
+        })
+    }
 
-        let access = match self.config.method_for(&full_path) {
-            Ok(Some(m)) => m,
-            _ => return self.not_found(&path_string),
+    // Picks the query parameters that the renderer is allowed to see
+    fn passthrough_params(&self, req: &mut Request) -> BTreeMap<String, String> {
+        if self.config.passthrough_params.is_empty() {
+            return BTreeMap::new();
+        }
+        let query = match req.get_ref::<UrlEncodedQuery>() {
+            Ok(q) => q,
+            Err(_) => return BTreeMap::new(),
         };
+        self.config.passthrough_params.iter()
+            .filter_map(|name| query.get(name)
+                .and_then(|v| v.first())
+                .map(|v| (name.clone(), v.clone())))
+            .collect()
+    }
 
-        // Does the path have a trailing slash?
-        let trailing_slash = match req.url.path().last() {
-            Some(&"") => true,
-            _ => false,
+    // The url that the client requested, before it was mounted
+    //
+    // Clients that do not send the Host header, such as some HTTP/1.0 clients,
+    // are given urls on the default host.
+    // Returns None if there is no default host to give them.
+    fn request_url(&self, req: &Request) -> Option<url::Url> {
+        let mut url : url::Url = match req.extensions.get::<mount::OriginalUrl>() {
+            Some(original_url) => original_url.clone().into(),
+            None => req.url.clone().into(),
         };
+        if req.headers.get::<Host>().is_none() {
+            let default_host = self.config.default_host.as_ref()?;
+            let host = url::Url::parse(&format!("http://{}/", default_host)).ok()?;
+            url.set_host(host.host_str()).ok()?;
+            url.set_port(host.port()).ok()?;
+        }
+        Some(url)
+    }
 
-        // Directories must have the trailing slash
-        // Files must not have the trailing slash
-        if trailing_slash && access.is_file()
-                || !trailing_slash && access.is_dir() {
-            return self.not_found(&path_string);
+    // Redirects the client to the url with or without the trailing slash, keeping the query
+    fn redirect_to_slash(&self, req: &Request, slash: bool) -> Option<IronResult<Response>> {
+        let mut url = self.request_url(req)?;
+        if slash {
+            url.path_segments_mut().ok()?.push("");
+        } else {
+            url.path_segments_mut().ok()?.pop_if_empty();
         }
-       
-        // If serving raw AND the path leads to a file
-        // Then serve the file directly
-        // Otherwise return error 404
-        if self.raw {
-            return if access.is_file() {
-                serve_raw(&full_path)
-            } else {
-                return self.not_found(&path_string);
+        Some(Ok(Response::with((
+            "Redirecting to canonical url.",
+            Redirect(Url::from_generic_url(url).ok()?),
+            status::MovedPermanently
+        ))))
+    }
+
+    // The time the listing of the directory was last modified
+    //
+    // This is when the directory itself was modified,
+    // or when any of its servable entries was if the configuration says so
+    fn listing_modified(&self, dir: &Path) -> Option<SystemTime> {
+        let modified = dir.metadata().and_then(|md| md.modified()).ok()?;
+        if !self.config.listing_modified_from_entries {
+            return Some(modified);
+        }
+        let newest_entry = fs::read_dir(dir).ok()?
+            .flatten()
+            .filter(|e| self.config.method_for(&e.path()).unwrap_or(None).is_some())
+            .filter_map(|e| e.metadata().and_then(|md| md.modified()).ok())
+            .max();
+        Some(newest_entry.map_or(modified, |m| m.max(modified)))
+    }
+
+    // Finds the newest file that the alias can serve, in the directory of the alias
+    fn newest_file(&self, alias: &LatestAlias) -> Option<PathBuf> {
+        let dir = self.root.join(alias.path.parent()?);
+        fs::read_dir(&dir).ok()?
+            .flatten()
+            .filter(|e| e.file_name().to_str().is_some_and(|n| alias.matches(n)))
+            .filter(|e| match self.config.method_for(&e.path()) {
+                Ok(Some(m)) => m.is_file(),
+                _ => false,
+            })
+            .filter(|e| self.config.time_until_written(&e.path()).is_none())
+            .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+            .max()
+            .map(|(_, path)| path)
+    }
+
+    // Looks for the configured index files in the directory that can be served,
+    // and picks the one whose Mime the client prefers, or else the first of them
+    // Returns the path to the file along with its access method,
+    // and whether or not there were several to pick from
+    fn index_file(&self, req: &Request, dir: &Path) -> Option<(PathBuf, AccessMethod, bool)> {
+        let mut found : Vec<(PathBuf, AccessMethod)> = self.config.index_files.iter()
+            .map(|name| dir.join(name))
+            .filter(|path| path.is_file() && self.config.time_until_written(path).is_none())
+            .filter_map(|path| match self.config.method_for(&path) {
+                Ok(Some(m)) if m.is_file() => Some((path, m)),
+                _ => None,
+            })
+            .collect();
+        if found.is_empty() {
+            return None;
+        }
+        let several = found.len() > 1;
+        let mimes : Vec<String> = found.iter()
+            .map(|(path, _)| {
+                let Mime(top, sub, _) = self.config.mime_for(path);
+                format!("{}/{}", top, sub)
+            })
+            .collect();
+        let candidates : Vec<&str> = mimes.iter().map(String::as_str).collect();
+        let picked = negotiation::preferred_type(req, &candidates)
+            .and_then(|preferred| candidates.iter().position(|&c| c == preferred))
+            .unwrap_or(0);
+        let (path, method) = found.swap_remove(picked);
+        Some((path, method, several))
+    }
+
+    // Whether or not the query asks for the directory as an archive, a feed, or JSON,
+    // any of which are served in place of its index file
+    fn listing_form_requested(&self, req: &mut Request) -> bool {
+        let query = match req.get_ref::<UrlEncodedQuery>() {
+            Ok(query) => query,
+            Err(_) => return false,
+        };
+        self.config.zip_download && is_zip_requested(query)
+            || self.config.feeds && is_feed_requested(query)
+            || self.config.json_listing && is_json_requested(query)
+    }
+
+    // Finds the file at the path without an extension,
+    // trying each of the configured extensions in order
+    fn extensionless_file(&self, full_path: &Path) -> Option<PathBuf> {
+        self.config.extensionless_extensions.iter()
+            .map(|ext| full_path.with_extension(ext))
+            .find(|p| match self.config.method_for(p) {
+                Ok(Some(m)) => m.is_file(),
+                _ => false,
+            })
+    }
+
+    // Redirects the client to the url with its last segment replaced by the name of the file,
+    // keeping the query
+    //
+    // Returns None if the url does not end with the requested path,
+    // such as when it has a trailing slash or backslash
+    fn redirect_to_file_name(
+        &self,
+        req: &Request,
+        path: &Path,
+        file: &Path,
+    ) -> Option<IronResult<Response>> {
+        let mut url = self.request_url(req)?;
+        let last = url.path_segments()?.next_back()
+            .and_then(|s| percent_decode(s.as_bytes()).decode_utf8().ok())
+            .map(|s| s.into_owned())?;
+        if path.file_name().and_then(|n| n.to_str()) != Some(last.as_str()) {
+            return None;
+        }
+        let name = file.file_name()?.to_str()?;
+        url.path_segments_mut().ok()?
+            .pop()
+            .push(name);
+        Some(Ok(Response::with((
+            "Redirecting to canonical url.",
+            Redirect(Url::from_generic_url(url).ok()?),
+            status::MovedPermanently
+        ))))
+    }
+
+    // Picks the variant of the file in the language preferred by the client,
+    // or in the default language if the client has no preference
+    //
+    // Variants are the servable files in the same directory, named after the file
+    // according to the configured pattern, such as `page.en.md' for `page.md'.
+    // If the file has no extension, the variants may have any extension.
+    fn language_variant(&self, req: &Request, full_path: &Path) -> Option<PathBuf> {
+        let dir = full_path.parent()?;
+        let requested = full_path.file_name()?.to_str()?;
+        let (name, extension) = match Path::new(requested).extension().and_then(|e| e.to_str()) {
+            Some(ext) => (&requested[..requested.len() - ext.len() - 1], Some(ext)),
+            None => (requested, None),
+        };
+        let pattern = self.config.language_pattern.replace("{name}", name);
+        let (prefix, suffix) = pattern.split_once("{lang}")?;
+
+        let mut variants : Vec<(String, PathBuf)> = fs::read_dir(dir).ok()?
+            .flatten()
+            .filter_map(|e| {
+                let path = e.path();
+                let file_name = e.file_name().into_string().ok()?;
+                let stem = match extension {
+                    Some(ext) if file_name.ends_with(&format!(".{}", ext)) =>
+                        String::from(&file_name[..file_name.len() - ext.len() - 1]),
+                    Some(_) => return None,
+                    None => Path::new(&file_name).file_stem()?.to_str()?.to_owned(),
+                };
+                if stem.len() <= prefix.len() + suffix.len()
+                        || !stem.starts_with(prefix) || !stem.ends_with(suffix) {
+                    return None;
+                }
+                let lang = &stem[prefix.len()..stem.len() - suffix.len()];
+                if lang.contains('.') {
+                    return None;
+                }
+                match self.config.method_for(&path) {
+                    Ok(Some(m)) if m.is_file() => Some((lang.to_owned(), path)),
+                    _ => None,
+                }
+            })
+            .collect();
+        variants.sort();
+
+        let languages : Vec<&str> = variants.iter().map(|v| v.0.as_str()).collect();
+        let language = negotiation::preferred_language(req, &languages)
+            .or_else(|| self.config.default_language.as_deref())?
+            .to_owned();
+        variants.into_iter()
+            .find(|v| v.0 == language)
+            .map(|v| v.1)
+    }
+
+    // Gathers the entries below the directories in the listing into trees,
+    // until the trees are as deep as configured or have as many entries as allowed
+    //
+    // Directories reached through symbolic links are only walked if they have not been already.
+    // Entries that cannot be listed are left out
+    fn grow_tree(
+        &self,
+        dir: &Path,
+        entries: Vec<Entry>,
+        order: Option<EntryOrder>,
+        reversed: bool,
+    ) -> Vec<TreeEntry> {
+        let mut budget = self.config.listing_tree_max_entries;
+        let mut tree = Vec::with_capacity(entries.len());
+        for entry in entries {
+            if !entry.is_dir {
+                tree.push(TreeEntry { entry, children: Vec::new(), truncated: false });
+                continue;
+            }
+            let limit = WalkLimit {
+                depth: self.config.listing_tree_depth - 2,
+                entries: budget,
+                follow_symlinks: true,
+            };
+            let walk = match walk_with_limit(&dir.join(&entry.raw_file_name), &self.config, limit) {
+                Ok(walk) => walk,
+                Err(_) => {
+                    tree.push(TreeEntry { entry, children: Vec::new(), truncated: false });
+                    continue;
+                },
+            };
+            budget -= walk.entries.len();
+            let mut by_parent : HashMap<&Path, Vec<&WalkedEntry>> = HashMap::new();
+            for w in &walk.entries {
+                by_parent.entry(w.rel_path.parent().unwrap_or(Path::new("")))
+                    .or_default()
+                    .push(w);
+            }
+            let children = self.branch(Path::new(""), &entry.href, &by_parent, order, reversed);
+            tree.push(TreeEntry { entry, children, truncated: walk.stopped_early });
+        }
+        tree
+    }
+
+    // Builds the branch of the tree from the walked entries in the directory,
+    // sorted in the same order as the listing
+    //
+    // The links are made relative to the directory being listed
+    fn branch(
+        &self,
+        rel_dir: &Path,
+        href: &str,
+        by_parent: &HashMap<&Path, Vec<&WalkedEntry>>,
+        order: Option<EntryOrder>,
+        reversed: bool,
+    ) -> Vec<TreeEntry> {
+        let mut gathered : Vec<(Gathered, (Entry, &WalkedEntry))> = by_parent.get(rel_dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|&w| {
+                let gathered = Gathered {
+                    file_name: w.path.file_name()?.to_owned(),
+                    path: w.path.clone(),
+                    metadata: Some(w.metadata.clone()),
+                };
+                let entry = entry_for(&self.config, &gathered)?.ok()?;
+                Some((gathered, (entry, w)))
+            })
+            .collect();
+        sort_entries(&mut gathered, order, reversed);
+        if self.config.dirs_first {
+            group_dirs_first(&mut gathered);
+        }
+        gathered.into_iter()
+            .map(|(_, (mut entry, w))| {
+                entry.href = format!("{}{}", href, entry.href.trim_start_matches("./"));
+                let children = if entry.is_dir {
+                    self.branch(&w.rel_path, &entry.href, by_parent, order, reversed)
+                } else {
+                    Vec::new()
+                };
+                TreeEntry { entry, children, truncated: w.truncated }
+            })
+            .collect()
+    }
+
+    // Counts the servable entries in the directory at the specified path
+    //
+    // The count is remembered until the modification time of the directory changes
+    fn count_children(&self, path: &Path) -> Option<usize> {
+        let modified = path.metadata().and_then(|md| md.modified()).ok()?;
+        if let Ok(mut counts) = self.child_counts.lock() {
+            if let Some(&(counted_at, count)) = counts.get(path) {
+                if counted_at == modified {
+                    return Some(count);
+                }
+            }
+        }
+
+        let count = fs::read_dir(path).ok()?
+            .flatten()
+            .filter(|e| {
+                self.config.method_for(&e.path())
+                    .unwrap_or(None)
+                    .is_some()
+            })
+            .count();
+        if let Ok(mut counts) = self.child_counts.lock() {
+            counts.insert(PathBuf::from(path), (modified, count));
+        }
+        Some(count)
+    }
+
+    // Reads the weight of the entry at the path from its sidecar
+    //
+    // The weight is remembered until the modification time of the sidecar changes
+    fn sidecar_weight(&self, path: &Path) -> Option<f64> {
+        let sidecar = sidecar_path(path, &self.config)?;
+        let modified = sidecar.metadata().and_then(|md| md.modified()).ok()?;
+        if let Ok(mut weights) = self.sidecar_weights.lock() {
+            if let Some(&(read_at, weight)) = weights.get(&sidecar) {
+                if read_at == modified {
+                    return weight;
+                }
+            }
+        }
+
+        let weight = read_sidecar_weight(&sidecar, &self.config);
+        if let Ok(mut weights) = self.sidecar_weights.lock() {
+            weights.insert(sidecar, (modified, weight));
+        }
+        weight
+    }
+
+    // Renders the Markdown script
+    //
+    // If the rendering fails and the configuration allows it
+    // then the source is rendered verbatim instead
+    fn render_markdown(&self, context: &RenderContext, content: &str) -> IronResult<Response> {
+        let result = markdown::render(content, &self.config);
+        let rendered = match self.renderer.render_markdown(context, &result) {
+            Err(_) if self.config.markdown_fallback_raw =>
+                self.renderer.render_verbatim(context, content),
+            r => r,
+        };
+        let preloads = match rendered {
+            Ok(ref page) if self.config.markdown_preload => self.preload_links(context, page),
+            _ => Vec::new(),
+        };
+        self.rendered_page(context, rendered).map(|mut r| {
+            if r.status == Some(status::Ok) {
+                for link in preloads {
+                    r.headers.append_raw("Link", link.into_bytes());
+                }
+            }
+            r
+        })
+    }
+
+    // Builds the values of the Link headers preloading the local assets of the rendered page
+    //
+    // Only the assets served as they are, rather than rendered, are preloaded,
+    // and only those whose types browsers can preload
+    fn preload_links(&self, context: &RenderContext, page: &str) -> Vec<String> {
+        let dir = path_of(context).parent().map(Path::to_path_buf).unwrap_or_default();
+        markdown::local_assets(page).into_iter()
+            .filter_map(|url| {
+                let without_query = url.split('?').next().unwrap_or("");
+                let decoded = percent_decode(without_query.as_bytes()).decode_utf8().ok()?;
+                // The asset must not be outside of the served root
+                let mut path = dir.clone();
+                for segment in decoded.split('/') {
+                    match segment {
+                        "" | "." => (),
+                        ".." => if !path.pop() {
+                            return None;
+                        },
+                        s => path.push(s),
+                    }
+                }
+                let full_path = self.root.join(&path);
+                match self.config.method_for(&full_path) {
+                    Ok(Some(AccessMethod::Raw)) => (),
+                    Ok(Some(m)) if self.raw && m.is_file() => (),
+                    _ => return None,
+                }
+                let destination = match self.config.mime_for(&full_path) {
+                    Mime(TopLevel::Image, _, _) => "image",
+                    Mime(TopLevel::Text, SubLevel::Css, _) => "style",
+                    Mime(_, SubLevel::Javascript, _) => "script",
+                    _ => return None,
+                };
+                Some(format!("<{}>; rel=preload; as={}", url, destination))
+            })
+            .collect()
+    }
+
+    // Reads the README of the root directory, if it can be served as Markdown
+    fn read_root_readme(&self) -> Option<String> {
+        let path = self.root.join("README.md");
+        match self.config.method_for(&path) {
+            Ok(Some(AccessMethod::Markdown)) => (),
+            _ => return None,
+        }
+        let mut content = String::new();
+        File::open(&path).ok()?
+            .read_to_string(&mut content).ok()?;
+        Some(content)
+    }
+
+    // Serves the directory at the specified path as a zip archive
+    //
+    // The archive is written as it is sent, so its length is not known beforehand
+    fn serve_zip(&self, context: &RenderContext, full_path: &Path) -> IronResult<Response> {
+        let stream = match download::zip_directory(full_path, &self.config) {
+            Ok(Ok(s)) => s,
+            Ok(Err(ZipRefusal::TooLarge)) => return self.error_page(
+                context,
+                403,
+                "The requested directory is too large to be downloaded",
+                status::Forbidden
+            ),
+            Ok(Err(ZipRefusal::TooDeep)) => return self.error_page(
+                context,
+                409,
+                "The requested directory is too deeply nested to be downloaded",
+                status::Conflict
+            ),
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        };
+
+        // Name the archive after the directory
+        let name = full_path.canonicalize().ok()
+            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .unwrap_or(String::from("archive"));
+        let mut response = Response::with((
+            status::Ok,
+            Header(ContentType(Mime(
+                TopLevel::Application,
+                SubLevel::Ext(String::from("zip")),
+                vec![]
+            )))
+        ));
+        response.body = Some(Box::new(stream));
+        response.headers.set_raw(
+            "Content-Disposition",
+            vec![attachment_disposition(&format!("{}.zip", name)).into_bytes()]
+        );
+        Ok(response)
+    }
+
+    // Serves the entries in the requested directory as an RSS feed
+    //
+    // Unless the query specifies an order, the newest entries come first
+    fn serve_feed(
+        &self,
+        req: &Request,
+        context: &RenderContext,
+        query: &QueryMap,
+        event: &mut AccessEvent,
+    ) -> IronResult<Response> {
+        // The entries are sorted before the page is picked, so that the first page is the newest
+        // Modification times that are hidden do not decide the order
+        let newest_first = match self.config.modified_precision {
+            ModifiedPrecision::Hidden => None,
+            _ => Some((EntryOrder::Chronological, true)),
+        };
+        let listing = match self.build_listing_in(&path_of(context), query, newest_first) {
+            Ok(listing) => listing,
+            Err(e) => return self.listing_error(context, &e),
+        };
+        event.skipped_entries = listing.skipped;
+
+        // Link the entries against the url of the directory as the client sees it
+        let mut link : url::Url = match req.extensions.get::<mount::OriginalUrl>() {
+            Some(original_url) => original_url.clone().into(),
+            None => req.url.clone().into(),
+        };
+        link.set_query(None);
+
+        let content = feed::rss(&format!("/{}", context.path), &link, &listing.entries);
+        Ok(Response::with((
+            content,
+            status::Ok,
+            Header(ContentType(Mime(
+                TopLevel::Application,
+                SubLevel::Ext(String::from("rss+xml")),
+                vec![(Attr::Charset, Value::Utf8)]
+            )))
+        )))
+    }
+
+    // Serves the listing of the directory as JSON
+    // in the version of the schema asked for, or in the latest one
+    fn serve_json(&self, context: &RenderContext, query: &QueryMap, event: &mut AccessEvent)
+            -> IronResult<Response> {
+        let version = match query.get("schema_version").and_then(|v| v.first()) {
+            Some(v) => match v.parse() {
+                Ok(v) => v,
+                Err(_) => return self.bad_request(context, "The schema version is invalid"),
+            },
+            None => *json::SCHEMA_VERSIONS.last().unwrap(),
+        };
+        let pretty = match query.get("pretty").and_then(|v| v.first()) {
+            Some(v) => v != "false" && v != "0",
+            None => self.config.json_pretty,
+        };
+        let listing = match self.build_listing(&path_of(context), query) {
+            Ok(listing) => listing,
+            Err(e) => return self.listing_error(context, &e),
+        };
+        event.skipped_entries = listing.skipped;
+        match json::listing(&context.path, &listing, version, pretty) {
+            Some(content) => Ok(Response::with((
+                content,
+                status::Ok,
+                Header(ContentType::json())
+            ))),
+            None => self.bad_request(context, "The schema version is not supported"),
+        }
+    }
+
+    // Serves the range of the text of the file as plain text
+    //
+    // The range is narrowed to whole UTF-8 characters,
+    // and the Content-Range header states the bytes actually sent
+    fn serve_text_range(
+        &self,
+        context: &RenderContext,
+        mut file: File,
+        range: &ByteRangeSpec,
+    ) -> IronResult<Response> {
+        let length = file.metadata()
+            .map_err(|e| IronError::new(e, status::InternalServerError))?
+            .len();
+        let (first, last) = match satisfiable_range(range, length) {
+            Some(r) => r,
+            None => return self.range_not_satisfiable(context, length),
+        };
+
+        let mut bytes = Vec::new();
+        file.seek(SeekFrom::Start(first))
+            .and_then(|_| file.take(last - first + 1).read_to_end(&mut bytes))
+            .map_err(|e| IronError::new(e, status::InternalServerError))?;
+        // Leave out the character split at the start of the range
+        let skipped = bytes.iter().take(3).take_while(|&&b| b & 0xC0 == 0x80).count();
+        // And the one split at the end
+        let text = match str::from_utf8(&bytes[skipped..]) {
+            Ok(text) => text,
+            Err(e) if e.error_len().is_none() =>
+                str::from_utf8(&bytes[skipped..skipped + e.valid_up_to()]).unwrap_or_default(),
+            Err(_) => return self.invalid_format(context),
+        };
+        if text.is_empty() {
+            return self.range_not_satisfiable(context, length);
+        }
+
+        let first = first + skipped as u64;
+        let last = first + text.len() as u64 - 1;
+        let mut response = response_text(String::from(text), SubLevel::Plain);
+        response.status = Some(status::PartialContent);
+        response.headers.set(ContentRange(ContentRangeSpec::Bytes {
+            range: Some((first, last)),
+            instance_length: Some(length),
+        }));
+        Ok(response)
+    }
+
+    // Serves the file as it is
+    //
+    // The file is opened by the caller, so that a file removed in the meantime
+    // can be told apart from one that cannot be sent
+    //
+    // The file is not sent again if the client already has this version of it
+    //
+    // Only the part of the file asked for is sent, if a single range is asked for
+    fn serve_raw(
+        &self,
+        req: &Request,
+        context: &RenderContext,
+        mut file: File,
+        mime: Mime,
+    ) -> IronResult<Response> {
+        let metadata = file.metadata()
+            .map_err(|e| IronError::new(e, status::InternalServerError))?;
+        let validators = file_validators(&metadata);
+        if let Some(ref v) = validators {
+            if v.is_fresh(req) {
+                return Ok(v.not_modified());
             }
         }
 
+        let range = single_range(req);
+        let mut response = match range.map(|r| satisfiable_range(r, metadata.len())) {
+            Some(Some((first, last))) => {
+                file.seek(SeekFrom::Start(first))
+                    .map_err(|e| IronError::new(e, status::InternalServerError))?;
+                let mut response = Response::with((
+                    mime,
+                    status::PartialContent,
+                    Header(ContentLength(last - first + 1)),
+                    Header(ContentRange(ContentRangeSpec::Bytes {
+                        range: Some((first, last)),
+                        instance_length: Some(metadata.len()),
+                    }))
+                ));
+                response.body = Some(Box::new(BodyReader(file.take(last - first + 1))));
+                response
+            },
+            Some(None) => return self.range_not_satisfiable(context, metadata.len()),
+            None => Response::with((file,
+                                    mime,
+                                    status::Ok)),
+        };
+        response.headers.set(AcceptRanges(vec![RangeUnit::Bytes]));
+        if let Some(v) = validators {
+            v.apply(&mut response);
+        }
+        Ok(response)
+    }
+
+    // Compresses the rendered page in the response with gzip if the client accepts it
+    //
+    // Only successful responses of the content types compressed by the configuration,
+    // and at least as large as their thresholds, are compressed
+    // The encoding is recorded in the event if the response is compressed
+    fn gzip_page(
+        &self,
+        req: &Request,
+        res: &mut Response,
+        context: &RenderContext,
+        full_path: &Path,
+        event: &mut AccessEvent,
+    ) {
+        let min_size = match (res.status, res.headers.get::<ContentType>()) {
+            (Some(status::Ok), Some(ContentType(mime))) => self.config.compression_threshold(mime),
+            _ => None,
+        };
+        let min_size = match min_size {
+            Some(size) => size,
+            None => return,
+        };
+        negotiation::add_vary(res, "Accept-Encoding");
+        // Responses may already have been compressed along with working out their validators
+        if !negotiation::accepts_gzip(req) || res.headers.has::<ContentEncoding>() {
+            return;
+        }
+
+        // The body may have been compressed for an earlier request for the same content
+        // Pages carrying a nonce differ on every request, so they are never kept
+        let key = match (&self.compressed_bodies, res.headers.get::<ETag>()) {
+            (Some(_), Some(ETag(tag))) if context.csp_nonce.is_none() =>
+                Some((full_path.to_path_buf(), tag.to_string(), "gzip")),
+            _ => None,
+        };
+        let kept = match (&self.compressed_bodies, &key) {
+            (Some(bodies), Some(key)) =>
+                bodies.lock().ok().and_then(|mut b| b.get(key).cloned()),
+            _ => None,
+        };
+        let compressed = match kept {
+            Some(compressed) => compressed,
+            None => {
+                let mut page = Vec::new();
+                if let Some(mut body) = res.body.take() {
+                    // Writing into memory cannot fail
+                    let _ = body.write_body(&mut page);
+                }
+                let compressed = if page.len() < min_size {
+                    None
+                } else {
+                    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(&page).and_then(|_| encoder.finish()).ok()
+                };
+                let compressed = match compressed {
+                    Some(compressed) => compressed,
+                    None => {
+                        res.body = Some(Box::new(page));
+                        return;
+                    },
+                };
+                if let (Some(bodies), Some(key)) = (&self.compressed_bodies, key) {
+                    if let Ok(mut bodies) = bodies.lock() {
+                        bodies.insert(key, compressed.clone());
+                    }
+                }
+                compressed
+            },
+        };
+        res.headers.set(ContentEncoding(vec![Encoding::Gzip]));
+        res.headers.set(ContentLength(compressed.len() as u64));
+        res.body = Some(Box::new(compressed));
+        event.encoding = Some(String::from("gzip"));
+    }
+
+    // Serves the content at the specified path using the specified access method
+    fn serve(
+        &self,
+        req: &mut Request,
+        context: &RenderContext,
+        full_path: &Path,
+        access: AccessMethod,
+        event: &mut AccessEvent,
+    ) -> IronResult<Response> {
         match access {
             AccessMethod::Markdown => {
                 // Serve the file rendered as Markdown
-                let mut file = match File::open(&full_path) {
+                let mut file = match File::open(full_path) {
                     Ok(f) => f,
-                    Err(_) => return self.not_found(&path_string),
+                    Err(_) => return self.not_found(context),
                 };
                 let mut content = String::new();
                 // If the file is UTF-8
                 // Then render the content of the file
                 // And render it as Markdown script
-                if let Ok(_) = file.read_to_string(&mut content) {
-                    let parser = Parser::new(&content);
-                    let mut result = String::new();
-                    html::push_html(&mut result, parser);
-                    self.renderer.render_markdown(&path_string, &result)
-                        .map(response_html)
+                if file.read_to_string(&mut content).is_ok() {
+                    if !self.config.markdown_negotiation {
+                        return self.render_markdown(context, &content);
+                    }
+                    // Serve the source instead if the client prefers it
+                    let preferred = negotiation::preferred_type(
+                        req,
+                        &["text/html", "text/markdown", "text/plain"]
+                    );
+                    let response = match preferred {
+                        Some("text/markdown") =>
+                            Ok(response_text(content, SubLevel::Ext(String::from("markdown")))),
+                        Some("text/plain") =>
+                            Ok(response_text(content, SubLevel::Plain)),
+                        _ => self.render_markdown(context, &content),
+                    };
+                    event.negotiated = preferred.is_some();
+                    let mut response = response?;
+                    negotiation::add_vary(&mut response, "Accept");
+                    if response.status != Some(status::Ok) {
+                        return Ok(response);
+                    }
+                    // The representation is compressed here rather than once it is served
+                    // So that its entity tag can tell whether it is
+                    let validators = file.metadata().ok().and_then(|md| file_validators(&md));
+                    if self.config.gzip {
+                        // The body compressed for the same representation may be kept
+                        // By the entity tag it has before it is compressed
+                        if let Some(ref v) = validators {
+                            representation_validators(v.clone(), &response).apply(&mut response);
+                        }
+                        self.gzip_page(req, &mut response, context, full_path, event);
+                    }
+                    let validators = validators.map(|v| representation_validators(v, &response));
+                    if let Some(v) = validators {
+                        if v.is_fresh(req) {
+                            let mut not_modified = v.not_modified();
+                            if let Some(vary) = response.headers.get_raw("Vary") {
+                                not_modified.headers.set_raw("Vary", vary.to_vec());
+                            }
+                            return Ok(not_modified);
+                        }
+                        v.apply(&mut response);
+                    }
+                    Ok(response)
                 // Otherwise there is an error
                 } else {
-                    self.invalid_format(&path_string)
+                    self.invalid_format(context)
                 }
             },
 
             AccessMethod::Verbatim => {
                 // Serve the unmodified text context of the file
-                let mut file = match File::open(&full_path) {
+                let mut file = match File::open(full_path) {
                     Ok(f) => f,
-                    Err(_) => return self.not_found(&path_string),
+                    Err(_) => return self.not_found(context),
                 };
+                // Or only the part of it asked for
+                if self.config.verbatim_ranges {
+                    if let Some(range) = single_range(req) {
+                        return self.serve_text_range(context, file, range);
+                    }
+                }
                 let mut content = String::new();
                 // If the file is UTF-8
                 // Then return the file as it is
-                if let Ok(_) = file.read_to_string(&mut content) {
-                    self.renderer.render_verbatim(&path_string, &content)
-                        .map(response_html)
+                if file.read_to_string(&mut content).is_ok() {
+                    if self.config.verbatim_line_numbers {
+                        let lines : Vec<&str> = content.lines().collect();
+                        let rendered = self.renderer.render_verbatim_lines(context, &lines);
+                        self.rendered_page(context, rendered)
+                    } else {
+                        let rendered = self.renderer.render_verbatim(context, &content);
+                        self.rendered_page(context, rendered)
+                    }
                 // Otherwise there is an error
                 } else {
-                    self.invalid_format(&path_string)
+                    self.invalid_format(context)
                 }
             },
 
             AccessMethod::Raw => {
-                serve_raw(&full_path)
+                // The file may have been removed since it was looked up
+                let mime = self.config.mime_for(&full_path);
+                match File::open(full_path) {
+                    Ok(f) => self.serve_raw(req, context, f, mime),
+                    Err(_) => self.not_found(context),
+                }
             },
 
             AccessMethod::Dir => {
-                // First collect the directory entries that we can access
-                let mut dir_entries : Vec<DirEntry> = fs::read_dir(full_path)
-                    .unwrap()
-                    .flat_map(|e| e)
-                    .filter(|e: &DirEntry| {
-                        self.config.method_for(&e.path())
-                            .unwrap_or(None)
-                            .is_some()
-                    } )
-                    .collect();
-
-                // Then sort the entries in the order specified
-                match get_entry_order(req) {
-                    Some(EntryOrder::Lexicographical) =>
-                        dir_entries.sort_by(cmp_entry_by_name),
+                // Read how the directory is asked to be listed
+                let query = req.get_ref::<UrlEncodedQuery>().cloned()
+                    .unwrap_or_default();
 
-                    Some(EntryOrder::Chronological) => 
-                        dir_entries.sort_by(cmp_entry_by_modified),
-
-                    None => (),
+                // The client may already have this listing
+                // Listings served as JSON are told apart from rendered ones
+                let json = self.config.json_listing && prefers_json(req);
+                let validators = if self.config.listing_validators {
+                    self.listing_modified(full_path)
+                        .and_then(|modified| listing_validators(req, modified, json))
+                } else {
+                    None
+                };
+                if let Some(ref v) = validators {
+                    if v.is_fresh(req) {
+                        let mut response = v.not_modified();
+                        if self.config.json_listing {
+                            negotiation::add_vary(&mut response, "Accept");
+                        }
+                        return Ok(response);
+                    }
                 }
-
-                // Then collect them as entry objects
-                let entries : Vec<Entry> = dir_entries.iter()
-                    .map(|de| Entry::from(de).unwrap())
-                    .collect();
-
-                // Render the page, generate an HTTP response
-                self.renderer.render_dir(&path_string, &entries)
-                    .map(response_html)
+                self.serve_listing(req, context, full_path, &query, event).map(|mut r| {
+                    if let Some(v) = validators {
+                        v.apply(&mut r);
+                    }
+                    if self.config.json_listing {
+                        negotiation::add_vary(&mut r, "Accept");
+                    }
+                    r
+                })
             },
         }
     }
-}
 
-// Wrap the rendered page in a response body
-fn response_html(content: String) -> Response {
-    Response::with((
-        content.as_str(),
-        status::Ok,
+    // Serves the listing of the directory in the form asked for by the query
+    fn serve_listing(
+        &self,
+        req: &mut Request,
+        context: &RenderContext,
+        full_path: &Path,
+        query: &QueryMap,
+        event: &mut AccessEvent,
+    ) -> IronResult<Response> {
+        // Pack the whole directory if the client asks for it
+        // Or if the directory is always downloaded
+        if self.config.zip_download && is_zip_requested(query)
+                || self.config.is_download_dir(&path_of(context)) {
+            return self.serve_zip(context, full_path);
+        }
+
+        // Serve the directory as a feed if the client asks for it
+        if self.config.feeds && is_feed_requested(query) {
+            return self.serve_feed(req, context, query, event);
+        }
+
+        // Likewise for JSON, which the client may also ask for with Accept
+        if self.config.json_listing && (is_json_requested(query) || prefers_json(req)) {
+            return self.serve_json(context, query, event);
+        }
+
+        let listing = match self.build_listing(&path_of(context), query) {
+            Ok(listing) => listing,
+            Err(e) => return self.listing_error(context, &e),
+        };
+        event.skipped_entries = listing.skipped;
+
+        // Render the page, generate an HTTP response
+        // And point to the pages before and after it, if there are any
+        let name = match self.config.pagination {
+            Pagination::Offset => "page",
+            Pagination::Cursor => "cursor",
+        };
+        let links : Vec<String> = [("prev", &listing.prev_page), ("next", &listing.next_page)]
+            .iter()
+            .filter_map(|&(rel, page)| {
+                let page = page.as_ref()?;
+                self.request_url(req).map(|url| page_link(url, name, page, rel))
+            })
+            .collect();
+        let context = &RenderContext {
+            page: listing.page_size.map(|size| ListingPage {
+                number: listing.page.map(|(number, _)| number),
+                count: listing.page.map(|(_, count)| count),
+                size,
+                prev: listing.prev_page.as_ref().map(|p| page_href(req, name, p)),
+                next: listing.next_page.as_ref().map(|p| page_href(req, name, p)),
+            }),
+            ..context.clone()
+        };
+        let rendered = if self.config.root_readme && is_root(&path_of(context)) {
+            // The README of the root takes the place of the listing if it can be read
+            match self.read_root_readme() {
+                Some(readme) => self.renderer.render_readme(
+                    context,
+                    &markdown::render(&readme, &self.config),
+                    &listing.entries
+                ),
+                None => self.renderer.render_dir(context, &listing.entries),
+            }
+        } else if self.config.listing_tree_depth > 1 {
+            let tree = self.grow_tree(
+                full_path,
+                listing.entries,
+                listing.order,
+                listing.reversed
+            );
+            self.renderer.render_dir_tree(context, &tree)
+        } else {
+            self.renderer.render_dir(context, &listing.entries)
+        };
+        self.rendered_page(context, rendered)
+            .map(|mut r| {
+                if r.status == Some(status::Ok) {
+                    for link in links {
+                        r.headers.append_raw("Link", link.into_bytes());
+                    }
+                }
+                r
+            })
+    }
+}
+
+impl<T> Handler for Archivist<T> where T: Renderer + Send + Sync + 'static {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let mut event = AccessEvent {
+            request_id: self.config.request_id_header.as_ref()
+                .map(|header| self.request_id(req, header)),
+            ..AccessEvent::default()
+        };
+        let mut result = self.respond(req, &mut event);
+
+        // Send the ID of the request back to the client
+        let request_id = self.config.request_id_header.as_ref()
+            .and_then(|header| event.request_id.as_ref().map(|id| (header, id)));
+        if let Some((header, id)) = request_id {
+            let headers = match result {
+                Ok(ref mut response) => &mut response.headers,
+                Err(ref mut e) => &mut e.response.headers,
+            };
+            headers.set_raw(header.clone(), vec![id.clone().into_bytes()]);
+        }
+
+        // State the charset of textual responses explicitly
+        // So that browsers do not have to guess it
+        match result {
+            Ok(ref mut response) => add_charset(response, &self.config.charset),
+            Err(ref mut e) => add_charset(&mut e.response, &self.config.charset),
+        }
+
+        // Answer errors in JSON to the clients preferring it
+        if self.config.json_errors {
+            match result {
+                Ok(ref mut response) => self.json_error(req, response),
+                Err(ref mut e) => self.json_error(req, &mut e.response),
+            }
+        }
+
+        // Answer HEAD requests with the headers alone
+        if req.method == Method::Head {
+            match result {
+                Ok(ref mut response) => strip_body(response),
+                Err(ref mut e) => strip_body(&mut e.response),
+            }
+        }
+
+        // Record how the request is handled
+        if let Some(ref log) = self.access_log {
+            event.status = match result {
+                Ok(ref response) => response.status,
+                Err(ref e) => e.response.status,
+            }.map(|s| s.to_u16());
+            log(&event);
+        }
+        result
+    }
+}
+
+impl<T> Archivist<T> where T: Renderer {
+    // Whether or not the client has logged in with the user name and password required
+    // Always true if logging in is not required
+    fn is_authorized(&self, req: &Request) -> bool {
+        let (user, password) = match (&self.config.auth_user, &self.config.auth_password) {
+            (Some(user), Some(password)) => (user, password),
+            _ => return true,
+        };
+        match req.headers.get::<Authorization<Basic>>() {
+            // Check both so that the time taken does not tell which one is wrong
+            Some(Authorization(basic)) => {
+                let password_given = basic.password.as_ref().map_or("", |p| p.as_str());
+                constant_time_eq(basic.username.as_bytes(), user.as_bytes())
+                    & constant_time_eq(password_given.as_bytes(), password.as_bytes())
+            },
+            None => false,
+        }
+    }
+
+    // Removes the longest matching prefix from the path of the request, as `mount' does,
+    // and lets the archivist of the root mounted there respond
+    fn respond_mounted(&self, req: &mut Request, event: &mut AccessEvent)
+            -> IronResult<Response> {
+        let matched = {
+            let path = req.url.path();
+            self.mounts.iter().find(|(prefix, _)| {
+                path.len() >= prefix.len() && path.iter().zip(prefix).all(|(s, p)| s == p)
+            })
+        };
+        let (length, archivist) = match matched {
+            Some((prefix, archivist)) => (prefix.len(), archivist),
+            None => return self.not_found(&RenderContext::default()),
+        };
+
+        // Redirects and links are then made against the url as the client sent it
+        let is_outer_mount = !req.extensions.contains::<mount::OriginalUrl>();
+        if is_outer_mount {
+            req.extensions.insert::<mount::OriginalUrl>(req.url.clone());
+        }
+        let url = req.url.clone();
+        let path = req.url.path()[length..].join("/");
+        req.url.as_mut().set_path(&path);
+
+        let response = archivist.respond(req, event);
+
+        req.url = url;
+        if is_outer_mount {
+            req.extensions.remove::<mount::OriginalUrl>();
+        }
+        response
+    }
+
+    // Handles the request, recording how it is handled in the event
+    fn respond(&self, req: &mut Request, event: &mut AccessEvent) -> IronResult<Response> {
+        // Answer health checks without touching the archives
+        if let Some(ref health_path) = self.config.health_path {
+            if req.url.path().join("/") == health_path.trim_matches('/') {
+                return Ok(self.health());
+            }
+        }
+
+        // Slow down the clients making too many requests
+        // Before checking their passwords, so that they cannot be guessed any faster
+        if let Some(ref throttle) = self.throttle {
+            if let Err(wait) = throttle.take(self.client_ip(req)) {
+                return self.too_many_requests(&RenderContext::default(), wait);
+            }
+        }
+
+        // Turn away the clients that have not logged in, if logging in is required
+        if !self.is_authorized(req) {
+            return self.unauthorized(&RenderContext::default());
+        }
+
+        // Likewise for the description of what the archivist can do
+        if let Some(ref capabilities_path) = self.config.capabilities_path {
+            if req.url.path().join("/") == capabilities_path.trim_matches('/') {
+                return Ok(Response::with((
+                    json::capabilities(&self.config),
+                    status::Ok,
+                    Header(ContentType::json())
+                )));
+            }
+        }
+
+        // Turn away the user agents that are not welcome
+        let user_agent = req.headers.get::<UserAgent>().map(|ua| ua.as_str());
+        if self.config.is_blocked_user_agent(user_agent) {
+            return self.blocked(&RenderContext::default());
+        }
+
+        // Let the archivist of the root mounted at the path respond, if there are mounts
+        if !self.mounts.is_empty() {
+            return self.respond_mounted(req, event);
+        }
+
+        // Reject pathologically long paths before doing any work on them
+        if req.url.path().len() > self.config.max_path_segments {
+            return self.uri_too_long(&RenderContext::default());
+        }
+
+        // Likewise for queries with too many parameters,
+        // before any of them are parsed
+        let query_params = req.url.query()
+            .map_or(0, |q| q.split('&').filter(|p| !p.is_empty()).count());
+        if query_params > self.config.max_query_params {
+            return self.bad_request(
+                &RenderContext::default(),
+                "The query has too many parameters"
+            );
+        }
+
+        // Construct the path to the file being accessed
+        //
+        // Empty segments are skipped: they only come from a trailing slash,
+        // or from the request path `/' at the root of the archive
+        let mut path = PathBuf::new();
+        let mut path_length = 0;
+        let mut trailing_backslash = false;
+        for n in req.url.path().into_iter().filter(|n| !n.is_empty()) {
+            // The path in the url is percent encoded
+            // So it needs to be decoded here
+            // No file can be served for a segment that is not valid UTF-8
+            let segment = match percent_decode(n.as_bytes()).decode_utf8() {
+                Ok(segment) => segment.into_owned(),
+                Err(_) => return self.not_found(&RenderContext::default()),
+            };
+            path_length += segment.len();
+            if path_length > self.config.max_path_length {
+                return self.uri_too_long(&RenderContext::default());
+            }
+            // Backslashes are treated as separators if the configuration says so
+            if self.config.normalize_backslashes {
+                trailing_backslash = segment.ends_with('\\');
+                for part in segment.split('\\').filter(|p| !p.is_empty()) {
+                    path.push(part);
+                }
+            } else {
+                path.push(segment);
+            }
+        }
+        let path_string = format!("{}", path.as_path().display());
+
+        // Send the clients at the root to the configured path, if any
+        // The query is kept, and the path is relative to where the archivist is mounted
+        if let Some(ref target) = self.config.root_redirect {
+            if is_root(&path) {
+                if let Some(mut url) = self.request_url(req) {
+                    url.path_segments_mut().unwrap()
+                        .pop_if_empty()
+                        .extend(target.trim_start_matches('/').split('/'));
+                    return Ok(Response::with((
+                        "Redirecting to the landing page.",
+                        Redirect(Url::from_generic_url(url).unwrap()),
+                        status::Found
+                    )));
+                }
+            }
+        }
+
+        // If we are at the root and that the archivist is mounted (using iron/mount)
+        // Then make sure that there is a trailing slash
+        //
+        // First check if the Archivist is mounted
+        if let Some(url) = req.extensions.get::<mount::OriginalUrl>() {
+            // Then check if the last part of the original url
+            // differs from the one we are now handling
+            //
+            // If there is a difference
+            // it indicates that a trailing slash have been added by the mount
+            //
+            // Without a host to redirect to, the root is served as it is
+            if url.path().last() != req.url.path().last() {
+                if let Some(mut original_url) = self.request_url(req) {
+                    // Redirect to the proper url (with trailing slash)
+                    original_url.path_segments_mut().unwrap().push("");
+                    return Ok(Response::with((
+                        "Redirecting to root directory.",
+                        Redirect(Url::from_generic_url(original_url).unwrap()),
+                        status::MovedPermanently
+                    )));
+                }
+            }
+        }
+
+        // Redirect to the canonical spelling of the url if it is spelt otherwise
+        // This keeps the urls consistent for caches
+        if self.config.canonical_urls {
+            if let Some(canonical) = self.request_url(req).and_then(|u| canonical_url(&u)) {
+                return Ok(Response::with((
+                    "Redirecting to canonical url.",
+                    Redirect(Url::from_generic_url(canonical).unwrap()),
+                    status::MovedPermanently
+                )));
+            }
+        }
+
+        // Construct the path to the actual file in the file system
+        let mut full_path = self.root.as_path().join(&path);
+
+        // If the path is an alias for the newest file in its directory
+        // And nothing else can be served there, then serve that file
+        if let Some(alias) = self.config.latest_aliases.iter().find(|a| a.path == path) {
+            if self.config.method_for(&full_path).unwrap_or(None).is_none() {
+                if let Some(newest) = self.newest_file(alias) {
+                    full_path = newest;
+                }
+            }
+        }
+
+        // Does the path have a trailing slash?
+        //
+        // The root of the archive is always accessed as a directory
+        // no matter how the (empty) request path is spelt
+        let trailing_slash = is_root(&path)
+            || trailing_backslash
+            || req.url.path().last() == Some(&"");
+
+        // If nothing can be served at a path without an extension
+        // Or only a directory which is not asked for with a trailing slash
+        // Then look for a file there with each of the configured extensions
+        let look_for_file = match self.config.method_for(&full_path).unwrap_or(None) {
+            Some(m) => m.is_dir() && !trailing_slash,
+            None => true,
+        };
+        if self.config.extensionless_urls && !is_root(&path) && path.extension().is_none()
+                && look_for_file {
+            if let Some(found) = self.extensionless_file(&full_path) {
+                if self.config.canonicalize_clean_urls
+                        && self.config.canonical_url_form == UrlForm::Extension {
+                    if let Some(response) = self.redirect_to_file_name(req, &path, &found) {
+                        return response;
+                    }
+                }
+                full_path = found;
+            }
+        }
+
+        // If the file would also be found without its extension
+        // Then send the client there if that is the canonical url
+        if self.config.extensionless_urls && self.config.canonicalize_clean_urls
+                && self.config.canonical_url_form == UrlForm::Clean && !is_root(&path) {
+            let clean = full_path.with_extension("");
+            let is_clean_url = path.extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| self.config.extensionless_extensions.iter().any(|x| x == e))
+                && clean.extension().is_none()
+                && self.config.method_for(&clean).unwrap_or(None).is_none_or(|m| m.is_dir())
+                && self.extensionless_file(&clean).as_ref() == Some(&full_path);
+            if is_clean_url {
+                if let Some(response) = self.redirect_to_file_name(req, &path, &clean) {
+                    return response;
+                }
+            }
+        }
+
+        // If the file cannot be served
+        // Then look for a variant of it in the language preferred by the client
+        let mut vary_language = false;
+        if self.config.language_variants && !is_root(&path)
+                && self.config.method_for(&full_path).unwrap_or(None).is_none() {
+            if let Some(variant) = self.language_variant(req, &full_path) {
+                full_path = variant;
+            }
+            vary_language = true;
+        }
+
+        // Only reveal where the file actually is if the configuration allows it
+        let context = RenderContext {
+            path: path_string,
+            fs_path: if self.config.expose_fs_paths {
+                full_path.canonicalize().ok()
+            } else {
+                None
+            },
+            params: self.passthrough_params(req),
+            csp_nonce: self.config.content_security_policy.as_ref().map(|_| csp_nonce()),
+            ..RenderContext::default()
+        };
+
+        event.path = context.path.clone();
+        event.rule = self.config.rule_for(&path.parent().unwrap_or(Path::new("")))
+            .map(|r| r.path.clone());
+
+        let access = match self.config.method_for(&full_path) {
+            Ok(Some(m)) => m,
+            _ => return self.not_found(&context),
+        };
+
+        // Files still being written are not served until they are complete
+        if let Some(remaining) = self.config.time_until_written(&full_path) {
+            return self.still_written(&context, remaining);
+        }
+
+        // Directories must have the trailing slash
+        // Files must not have the trailing slash
+        // Unless the configuration says so, the client is not told which it is
+        if trailing_slash && access.is_file()
+                || !trailing_slash && access.is_dir() {
+            if self.config.trailing_slash_redirects && !trailing_backslash {
+                if let Some(response) = self.redirect_to_slash(req, access.is_dir()) {
+                    return response;
+                }
+            }
+            return self.not_found(&context);
+        }
+
+        // Serve the index file of the directory in place of its listing, if it has one
+        // Unless the client asks for the listing in another form
+        // The client may pick between several of them with Accept
+        let index = if access.is_dir() && !self.listing_form_requested(req) {
+            self.index_file(req, &full_path)
+        } else {
+            None
+        };
+        let mut vary_accept = false;
+        let (context, full_path, access) = match index {
+            Some((index_path, index_access, several)) => {
+                vary_accept = several;
+                let context = RenderContext {
+                    fs_path: context.fs_path.as_ref().and_then(|_| index_path.canonicalize().ok()),
+                    ..context
+                };
+                (context, index_path, index_access)
+            },
+            None => (context, full_path, access),
+        };
+       
+        // If serving raw AND the path leads to a file
+        // Then serve the file directly
+        // Otherwise return error 404
+        if self.raw && access.is_dir() {
+            return self.not_found(&context);
+        }
+        let access = if self.raw { AccessMethod::Raw } else { access };
+        event.method = Some(access);
+
+        // The url that search engines should index this content under
+        let link = if self.config.canonical_link {
+            self.request_url(req).map(canonical_link)
+        } else {
+            None
+        };
+
+        // Serve the content
+        // And tell the downstream caches how long it may be kept
+        self.serve(req, &context, &full_path, access, event).map(|mut response| {
+            // Rendered pages are generated anew for every request
+            // So parts of them cannot be requested
+            // Though parts of the text of files served verbatim can be, if allowed
+            if access == AccessMethod::Verbatim && self.config.verbatim_ranges {
+                response.headers.set(AcceptRanges(vec![RangeUnit::Bytes]));
+            } else if access != AccessMethod::Raw {
+                response.headers.set(AcceptRanges(vec![RangeUnit::None]));
+            }
+            if vary_language {
+                negotiation::add_vary(&mut response, "Accept-Language");
+            }
+            if vary_accept {
+                negotiation::add_vary(&mut response, "Accept");
+            }
+            if self.config.gzip && access != AccessMethod::Raw {
+                self.gzip_page(req, &mut response, &context, &full_path, event);
+            }
+            // Parts of the content, and answers that the cached content is still fresh,
+            // may be kept as long as the whole content
+            match response.status {
+                Some(status::Ok) | Some(status::PartialContent) | Some(status::NotModified) => {
+                    if access.is_file() && self.config.cache.is_immutable(&full_path) {
+                        response.headers.set(immutable_cache_control());
+                    } else if let Some(max_age) =
+                            self.config.cache.max_age_for(&full_path, access) {
+                        response.headers.set(cache_control(max_age));
+                    }
+                },
+                _ => (),
+            }
+            if response.status == Some(status::Ok) {
+                if let Some(link) = link {
+                    response.headers.append_raw("Link", link.into_bytes());
+                }
+            }
+            response
+        })
+    }
+}
+
+// Starts the threads inspecting the entries of directories
+// No threads are started if the entries are to be inspected one at a time
+fn stat_pool_for(config: &Config) -> Option<Pool> {
+    if config.stat_concurrency > 1 {
+        Some(Pool::new(config.stat_concurrency))
+    } else {
+        None
+    }
+}
+
+// Sets aside the room for the responses already compressed
+// Nothing is set aside if they are not to be kept
+fn compressed_bodies_for(config: &Config) -> Option<CompressedBodies> {
+    if config.compression_cache_size > 0 {
+        Some(Mutex::new(Lru::new(config.compression_cache_size)))
+    } else {
+        None
+    }
+}
+
+// Builds the throttle limiting the requests of each client, if the configuration asks for one
+fn throttle_for(config: &Config) -> Option<Throttle> {
+    config.rate_limit.map(|limit| {
+        Throttle::new(limit, Duration::from_secs(config.rate_limit_window_secs))
+    })
+}
+
+// Tells the client when to try again
+// The time is rounded up, so that the client does not come back too early
+fn set_retry_after(res: &mut Response, wait: Duration) {
+    let seconds = wait.as_secs() + if wait.subsec_nanos() > 0 { 1 } else { 0 };
+    res.headers.set_raw("Retry-After", vec![seconds.to_string().into_bytes()]);
+}
+
+// Generates a random 128-bit ID, written in hex
+fn random_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+// Generates a nonce for the Content-Security-Policy header, written in hex
+//
+// The nonce is drawn from a cryptographically secure generator,
+// as it must not be guessed by the author of an injected script
+fn csp_nonce() -> String {
+    format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>())
+}
+
+// The error that an error page is about
+struct ErrorDetails {
+    code: u16,
+    message: String,
+    // The path requested, relative to the served root
+    path: String,
+}
+
+impl Key for ErrorDetails {
+    type Value = ErrorDetails;
+}
+
+// States that the response is to be saved as a file with the specified name
+//
+// Clients that do not understand names in UTF-8 are given a name with only
+// the printable ASCII characters kept
+fn attachment_disposition(name: &str) -> String {
+    let fallback : String = name.chars()
+        .map(|c| match c {
+            ' '..='~' if c != '\\' && c != '"' => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded : String = name.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9'
+                | b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.'
+                | b'^' | b'_' | b'`' | b'|' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+// Wrap the rendered page in a response body
+fn response_html(content: String) -> Response {
+    Response::with((
+        content.as_str(),
+        status::Ok,
         Header(ContentType::html())
     ))
 }
 
-// Stock response bodies
-#[inline]
-fn serve_raw<P: AsRef<Path>>(full_path: &P) -> IronResult<Response> {
-    Ok(Response::with((full_path.as_ref(),
-                       status::Ok)))
+// Wrap the text in a response body of the specified text subtype
+fn response_text(content: String, subtype: SubLevel) -> Response {
+    let mime = Mime(TopLevel::Text, subtype, vec![(Attr::Charset, Value::Utf8)]);
+    Response::with((
+        content,
+        status::Ok,
+        Header(ContentType(mime))
+    ))
+}
+
+// Drops the body of the response, but keeps stating its length
+//
+// Files are then never read, though the length of a body without one cannot be stated
+fn strip_body(res: &mut Response) {
+    res.body = match res.body {
+        Some(_) if res.headers.has::<ContentLength>() => Some(Box::new(Vec::<u8>::new())),
+        _ => None,
+    };
 }
 
+// Adds the charset to the Content-Type of a textual response if it has none
+//
+// Rendered pages are always stated to be UTF-8 already,
+// so this only affects text files served raw
+fn add_charset(res: &mut Response, charset: &str) {
+    let mime = match res.headers.get::<ContentType>() {
+        Some(&ContentType(Mime(TopLevel::Text, ref sub, ref params)))
+                if !params.iter().any(|p| p.0 == Attr::Charset) => {
+            let mut params = params.clone();
+            params.push((Attr::Charset, charset.parse().unwrap_or(Value::Utf8)));
+            Mime(TopLevel::Text, sub.clone(), params)
+        },
+        _ => return,
+    };
+    res.headers.set(ContentType(mime));
+}
+
+// Builds the Cache-Control header for the specified maximum age in seconds
+// A maximum age of zero means that the content must be revalidated every time
 #[inline]
-fn get_entry_order(req: &mut Request) -> Option<EntryOrder> {
-    if let Ok(ref queries) = req.get_ref::<UrlEncodedQuery>() {
-        queries.get("order")
-            .and_then(|v| v.first())
-            .and_then(|o| match o.as_str() {
-                "lexicographical" => Some(EntryOrder::Lexicographical),
-                "chronological" => Some(EntryOrder::Chronological),
-                _ => None
-            } )
+fn cache_control(max_age: u64) -> CacheControl {
+    if max_age == 0 {
+        CacheControl(vec![CacheDirective::NoCache])
     } else {
+        CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(max_age.min(u64::from(u32::MAX)) as u32),
+        ])
+    }
+}
+
+// Builds the Cache-Control header for content that never changes
+#[inline]
+fn immutable_cache_control() -> CacheControl {
+    CacheControl(vec![
+        CacheDirective::Public,
+        CacheDirective::MaxAge(31536000),
+        CacheDirective::Extension(String::from("immutable"), None),
+    ])
+}
+
+// The single range of bytes asked for by the client
+//
+// Returns None if no range, or more than one, is asked for,
+// or if the range ends before it starts, so that the whole content is sent instead
+fn single_range<'a>(req: &'a Request) -> Option<&'a ByteRangeSpec> {
+    match req.headers.get::<Range>() {
+        Some(Range::Bytes(ranges)) if ranges.len() == 1 => match ranges[0] {
+            ByteRangeSpec::FromTo(from, to) if to < from => None,
+            ref range => Some(range),
+        },
+        _ => None,
+    }
+}
+
+// The first and last bytes of the range, within the length of the content
+// Returns None if none of the range is within it
+fn satisfiable_range(range: &ByteRangeSpec, length: u64) -> Option<(u64, u64)> {
+    match *range {
+        _ if length == 0 => None,
+        ByteRangeSpec::FromTo(from, to) if from < length =>
+            Some((from, to.min(length - 1))),
+        ByteRangeSpec::AllFrom(from) if from < length => Some((from, length - 1)),
+        ByteRangeSpec::Last(n) if n > 0 => Some((length - n.min(length), length - 1)),
+        _ => None,
+    }
+}
+
+// The validators of a file, derived from its length and the time it was last modified
+fn file_validators(md: &Metadata) -> Option<Validators> {
+    let modified = md.modified().ok()?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+    let etag = EntityTag::weak(format!(
+        "{:x}-{:x}.{:x}",
+        md.len(),
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos()
+    ));
+    Some(Validators::new(etag, modified))
+}
+
+// The validators of the file, with entity tags told apart by the type and the coding
+// of the representation of it in the response
+fn representation_validators(v: Validators, res: &Response) -> Validators {
+    let subtype = match res.headers.get::<ContentType>() {
+        Some(ContentType(Mime(_, sub, _))) => sub.to_string(),
+        None => String::new(),
+    };
+    let coding = if res.headers.has::<ContentEncoding>() { "+gzip" } else { "" };
+    Validators {
+        etag: EntityTag::weak(format!("{}-{}{}", v.etag.tag(), subtype, coding)),
+        ..v
+    }
+}
+
+// Re-encodes each segment of the path in the url in the canonical way
+// Returns None if the url is already canonical
+//
+// Encoded slashes stay encoded, since they are part of the segments
+fn canonical_url(url: &url::Url) -> Option<url::Url> {
+    let segments = url.path_segments()?
+        .map(|s| percent_decode(s.as_bytes())
+                    .decode_utf8()
+                    .map(|s| s.into_owned())
+                    .ok())
+        .collect::<Option<Vec<String>>>()?;
+    let mut canonical = url.clone();
+    canonical.path_segments_mut().ok()?
+        .clear()
+        .extend(segments.iter());
+    if canonical.path() == url.path() {
         None
+    } else {
+        Some(canonical)
     }
 }
 
-// Comparers for DirEntry
-fn cmp_entry_by_name(e1: &DirEntry, e2: &DirEntry) -> Ordering {
-    // TODO: implement naturalistic comparison of strings
-    e1.file_name().cmp(&e2.file_name())
+// Builds the value of the Link header pointing to another page of a listing,
+// such as the `next' one
+// The rest of the query, such as the order, is kept
+fn page_link(mut url: url::Url, name: &str, value: &str, rel: &str) -> String {
+    let query : Vec<(String, String)> = url.query_pairs()
+        .filter(|(n, _)| n != name)
+        .map(|(n, v)| (n.into_owned(), v.into_owned()))
+        .collect();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(query)
+        .append_pair(name, value);
+    url.set_fragment(None);
+    format!("<{}>; rel=\"{}\"", url, rel)
 }
 
-fn cmp_entry_by_modified(e1: &DirEntry, e2: &DirEntry) -> Ordering {
-    try_cmp_entry_by_modified(e1, e2).unwrap_or(Ordering::Equal)
+// Builds the url to another page of a listing, relative to the listing
+// The rest of the query is kept likewise
+fn page_href(req: &Request, name: &str, value: &str) -> String {
+    let url : url::Url = req.url.clone().into();
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    query.extend_pairs(url.query_pairs().filter(|(n, _)| n != name))
+        .append_pair(name, value);
+    format!("?{}", query.finish())
 }
 
-fn try_cmp_entry_by_modified(e1: &DirEntry, e2: &DirEntry)
-        -> Result<Ordering, io::Error> {
-    let e1_meta = e1.metadata()?;
-    let e1_modified = e1_meta.modified()?;
+// Builds the value of the Link header pointing to the canonical url
+//
+// The query is dropped, so that sorted and filtered listings
+// are indexed under the same url as the plain one
+fn canonical_link(mut url: url::Url) -> String {
+    url.set_query(None);
+    url.set_fragment(None);
+    let url = canonical_url(&url).unwrap_or(url);
+    format!("<{}>; rel=\"canonical\"", url)
+}
 
-    let e2_meta = e2.metadata()?;
-    let e2_modified = e2_meta.modified()?;
+// Whether or not the query asks for the directory as a zip archive
+#[inline]
+fn is_zip_requested(query: &QueryMap) -> bool {
+    query.get("download")
+        .and_then(|v| v.first())
+        .is_some_and(|d| d == "zip")
+}
 
-    Ok(e1_modified.cmp(&e2_modified))
+// Whether or not the query asks for the directory as a feed
+#[inline]
+fn is_feed_requested(query: &QueryMap) -> bool {
+    query.get("format")
+        .and_then(|v| v.first())
+        .is_some_and(|f| f == "rss")
 }
 
+// The validators of the listing of the directory, derived from the time
+// the listing was last modified, the query it is listed with,
+// and whether it is served as JSON
+fn listing_validators(req: &Request, modified: SystemTime, json: bool) -> Option<Validators> {
+    let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+    let mut hasher = DefaultHasher::new();
+    req.url.query().hash(&mut hasher);
+    json.hash(&mut hasher);
+    let etag = EntityTag::weak(format!(
+        "{:x}.{:x}-{:x}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos(),
+        hasher.finish()
+    ));
+    Some(Validators::new(etag, modified))
+}
+
+// Whether or not the query asks for the directory as JSON
+#[inline]
+fn is_json_requested(query: &QueryMap) -> bool {
+    query.get("format")
+        .and_then(|v| v.first())
+        .is_some_and(|f| f == "json")
+}
+
+// Whether or not the client prefers the listing as JSON to the rendered page
+#[inline]
+fn prefers_json(req: &Request) -> bool {
+    negotiation::preferred_type(req, &["text/html", "application/json"])
+        == Some("application/json")
+}
+
+// The path requested, relative to the served root
+#[inline]
+fn path_of(context: &RenderContext) -> PathBuf {
+    PathBuf::from(&context.path)
+}
+
+// Whether or not the path refers to the root of the archive
+#[inline]
+fn is_root(path: &Path) -> bool {
+    path.as_os_str().is_empty()
+}
+
+// Compares two strings of bytes in a time depending only on the length of the first one,
+// so that the time taken does not tell how much of a secret has been guessed
+fn constant_time_eq(given: &[u8], expected: &[u8]) -> bool {
+    let mut diff = given.len() ^ expected.len();
+    for (i, &b) in given.iter().enumerate() {
+        let e = expected.get(i % expected.len().max(1)).cloned().unwrap_or(0);
+        diff |= usize::from(b ^ e);
+    }
+    diff == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use iron::Response;
+    use flate2::read::GzDecoder;
+    use zip::ZipArchive;
+
+    use std::collections::BTreeSet;
+    use std::ffi::OsString;
+    use std::fs::File;
+    use std::io::Cursor;
+    use std::io::prelude::*;
+    use std::path::PathBuf;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use config::*;
+    use testing::*;
+    use super::Archivist;
+
+    // Serves everything in the directory with the test renderer
+    fn config_for(dir: &TempDir) -> Config {
+        Config {
+            root_dir: dir.path().to_string_lossy().into_owned(),
+            allow_all: true,
+            ..Config::default()
+        }
+    }
+
+    fn rule(path: &str, block: &[&str]) -> Rule {
+        Rule {
+            path: PathBuf::from(path),
+            allow: BTreeSet::new(),
+            block: block.iter().map(OsString::from).collect(),
+            force_raw: BTreeSet::new(),
+            force_verbatim: BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn failed_logins_are_throttled() {
+        let dir = TempDir::new();
+        dir.file("a.txt", "a");
+        let config = Config {
+            auth_user: Some(String::from("user")),
+            auth_password: Some(String::from("password")),
+            rate_limit: Some(2),
+            rate_limit_window_secs: 3600,
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+
+        // `user:wrong'
+        let head = "GET /a.txt HTTP/1.1\r\nHost: localhost\r\n\
+                    Authorization: Basic dXNlcjp3cm9uZw==\r\n";
+        assert_eq!(request(address, head).status, 401);
+        assert_eq!(request(address, head).status, 401);
+        let response = request(address, head);
+        assert_eq!(response.status, 429);
+        assert!(response.header("Retry-After").is_some());
+    }
+
+    #[test]
+    fn clients_over_the_rate_limit_are_told_when_to_come_back() {
+        let dir = TempDir::new();
+        dir.file("a.txt", "a");
+        let config = Config {
+            rate_limit: Some(1),
+            rate_limit_window_secs: 60,
+            client_ip_header: Some(String::from("X-Forwarded-For")),
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+        let from = |ip: &str| request(address, &format!(
+            "GET /a.txt HTTP/1.1\r\nHost: localhost\r\nX-Forwarded-For: 10.0.0.9, {}\r\n",
+            ip
+        ));
+
+        assert_eq!(from("192.0.2.1").status, 200);
+        let res = from("192.0.2.1");
+        assert_eq!(res.status, 429);
+        let retry_after : u64 = res.header("Retry-After").unwrap().parse().unwrap();
+        assert!(retry_after > 58 && retry_after <= 60, "{}", retry_after);
+        // The client is told apart by the last address in the header
+        assert_eq!(from("192.0.2.2").status, 200);
+    }
+
+    #[test]
+    fn waits_are_rounded_up_to_whole_seconds() {
+        for &(wait, seconds) in &[
+            (Duration::from_secs(0), "0"),
+            (Duration::from_millis(1), "1"),
+            (Duration::from_millis(1200), "2"),
+            (Duration::from_secs(3), "3"),
+        ] {
+            let mut res = Response::new();
+            super::set_retry_after(&mut res, wait);
+            assert_eq!(res.headers.get_raw("Retry-After").unwrap()[0], seconds.as_bytes());
+        }
+    }
+
+    #[test]
+    fn unknown_extensions_are_sent_with_the_default_mime() {
+        let dir = TempDir::new();
+        dir.file("a.unknownext", "a");
+        let address = serve(Archivist::summon(&config_for(&dir), Arc::new(TestRenderer)));
+        let response = get(address, "/a.unknownext");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Content-Type"), Some("application/octet-stream"));
+
+        let config = Config {
+            default_mime: "application/x-archive".parse().unwrap(),
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+        let response = get(address, "/a.unknownext");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Content-Type"), Some("application/x-archive"));
+    }
+
+    #[test]
+    fn scoped_rules_apply_under_mounted_roots() {
+        let dir = TempDir::new();
+        dir.file("docs/secret/a.txt", "hidden");
+        dir.file("docs/a.txt", "shown");
+        let config = Config {
+            rules: vec![rule("secret", &["a.txt"])],
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon_multi(
+            &config,
+            vec![(String::from("docs"), dir.path().join("docs"))],
+            Arc::new(TestRenderer)
+        ));
+
+        assert_eq!(get(address, "/docs/secret/a.txt").status, 404);
+        let response = get(address, "/docs/a.txt");
+        assert_eq!(response.status, 200);
+        assert!(response.text().ends_with("shown"));
+    }
+
+    #[test]
+    fn entries_inspected_on_several_threads_are_sorted() {
+        let dir = TempDir::new();
+        for i in 0..20 {
+            dir.file(&format!("f{}.txt", i), &"x".repeat(20 - i));
+        }
+        dir.file("sub/a.txt", "a");
+        let config = Config {
+            stat_concurrency: 3,
+            dirs_first: true,
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+
+        let mut expected = vec![String::from("dir /"), String::from("sub/")];
+        expected.extend((0..20).rev().map(|i| format!("f{}.txt", i)));
+        let res = get(address, "/?order=size");
+        assert_eq!(res.status, 200);
+        assert_eq!(res.text().lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn directories_are_streamed_as_zip_archives() {
+        let dir = TempDir::new();
+        dir.file("Déjà vu/a.txt", "first");
+        dir.file("Déjà vu/sub/b.txt", &"second ".repeat(1000));
+        let config = Config {
+            zip_download: true,
+            zip_max_size: 8000,
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+
+        let res = get(address, "/D%C3%A9j%C3%A0%20vu/?download=zip");
+        assert_eq!(res.status, 200);
+        assert_eq!(res.header("Transfer-Encoding"), Some("chunked"));
+        assert_eq!(
+            res.header("Content-Disposition"),
+            Some("attachment; filename=\"D_j_ vu.zip\"; filename*=UTF-8''D%C3%A9j%C3%A0%20vu.zip")
+        );
+        let mut archive = ZipArchive::new(Cursor::new(res.body)).unwrap();
+        assert_eq!(archive.len(), 2);
+        let mut content = String::new();
+        archive.by_name("sub/b.txt").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "second ".repeat(1000));
+        content.clear();
+        archive.by_name("a.txt").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "first");
+
+        dir.file("Déjà vu/c.txt", &"x".repeat(1000));
+        let res = get(address, "/D%C3%A9j%C3%A0%20vu/?download=zip");
+        assert_eq!(res.status, 403);
+    }
+
+    #[test]
+    fn index_files_do_not_shadow_other_forms_of_the_listing() {
+        let dir = TempDir::new();
+        let index = dir.file("docs/index.txt", "welcome");
+        dir.file("docs/a.txt", "a");
+        let config = Config {
+            index_files: vec![String::from("index.txt")],
+            expose_fs_paths: true,
+            json_listing: true,
+            zip_download: true,
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+
+        let res = get(address, "/docs/");
+        assert_eq!(res.status, 200);
+        assert_eq!(
+            res.text(),
+            format!("verbatim /docs {}\nwelcome", index.canonicalize().unwrap().display())
+        );
+
+        let res = get(address, "/docs/?format=json");
+        assert_eq!(res.status, 200);
+        assert!(res.header("Content-Type").unwrap().starts_with("application/json"));
+        assert!(res.text().contains("a.txt"));
+
+        let res = get(address, "/docs/?download=zip");
+        assert_eq!(res.status, 200);
+        assert_eq!(ZipArchive::new(Cursor::new(res.body)).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn index_files_are_negotiated_with_accept() {
+        let dir = TempDir::new();
+        dir.file("api/index.html", "<p>hello</p>");
+        dir.file("api/index.json", "{}");
+        dir.file("only/index.html", "<p>only</p>");
+        let config = Config {
+            index_files: vec![String::from("index.html"), String::from("index.json")],
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+        let accepting = |path: &str, accept: &str| request(address, &format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept: {}\r\n",
+            path,
+            accept
+        ));
+
+        let res = accepting("/api/", "application/json");
+        assert_eq!(res.text(), "{}");
+        assert_eq!(res.header("Vary"), Some("Accept"));
+        let res = accepting("/api/", "text/html,application/json;q=0.9");
+        assert_eq!(res.text(), "verbatim /api\n<p>hello</p>");
+        assert_eq!(res.header("Vary"), Some("Accept"));
+        // The first listed is served if the client prefers neither
+        let res = accepting("/api/", "image/png");
+        assert_eq!(res.text(), "verbatim /api\n<p>hello</p>");
+        assert_eq!(get(address, "/api/").text(), "verbatim /api\n<p>hello</p>");
+
+        // There is nothing to pick from with a single index file
+        let res = accepting("/only/", "application/json");
+        assert_eq!(res.text(), "verbatim /only\n<p>only</p>");
+        assert_eq!(res.header("Vary"), None);
+    }
+
+    #[test]
+    fn directories_deeper_than_allowed_are_not_zipped() {
+        let dir = TempDir::new();
+        dir.file("top/a.txt", "a");
+        dir.file("top/1/2/b.txt", "b");
+        let config = Config {
+            zip_download: true,
+            max_depth: 1,
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+
+        let res = get(address, "/top/?download=zip");
+        assert_eq!(res.status, 409);
+        assert_eq!(get(address, "/top/1/?download=zip").status, 200);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn trees_are_cut_off_and_do_not_loop() {
+        use std::os::unix::fs::symlink;
+
+        let dir = TempDir::new();
+        dir.file("a/b/c/deep.txt", "");
+        dir.file("a/b/one.txt", "");
+        dir.file("z/1.txt", "");
+        dir.file("z/2.txt", "");
+        symlink(dir.path().join("a"), dir.path().join("a/b/up")).unwrap();
+        let config = Config {
+            listing_tree_depth: 4,
+            listing_tree_max_entries: 6,
+            dirs_first: true,
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+
+        let res = get(address, "/?order=lexicographical");
+        assert_eq!(res.text(), [
+            "tree /",
+            "a/",
+            "  a/b/",
+            "    a/b/c/",
+            "      a/b/c/deep.txt",
+            "    a/b/up/",
+            "    a/b/one.txt",
+            "z/",
+            "  z/1.txt",
+            "  ...",
+            "",
+        ].join("\n"));
+        // Directories at the depth of the tree are cut off
+        let config = Config { listing_tree_depth: 3, ..config };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+        let res = get(address, "/a/?order=lexicographical");
+        assert_eq!(res.text(), [
+            "tree /a",
+            "b/",
+            "  b/c/",
+            "    b/c/deep.txt",
+            "  b/up/",
+            "    b/up/b/",
+            "      ...",
+            "  b/one.txt",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn feeds_are_sorted_before_they_are_paged() {
+        let dir = TempDir::new();
+        for (i, name) in ["old.txt", "new.txt", "middle.txt"].iter().enumerate() {
+            let modified = match i {
+                0 => 1_000_000,
+                1 => 3_000_000,
+                _ => 2_000_000,
+            };
+            File::options().write(true).open(dir.file(name, ""))
+                .and_then(|f| f.set_modified(UNIX_EPOCH + Duration::from_secs(modified)))
+                .unwrap();
+        }
+        let config = Config {
+            feeds: true,
+            page_size: Some(2),
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+
+        let feed = get(address, "/?format=rss").text();
+        let new = feed.find("new.txt").unwrap();
+        let middle = feed.find("middle.txt").unwrap();
+        assert!(new < middle);
+        assert!(!feed.contains("old.txt"));
+        assert!(get(address, "/?format=rss&page=2").text().contains("old.txt"));
+    }
+
+    #[test]
+    fn ranges_ending_before_they_start_are_ignored() {
+        let dir = TempDir::new();
+        dir.file("data.bin", "0123456789");
+        let address = serve(Archivist::summon(&config_for(&dir), Arc::new(TestRenderer)));
+        let range = |range: &str| request(address, &format!(
+            "GET /data.bin HTTP/1.1\r\nHost: localhost\r\nRange: bytes={}\r\n",
+            range
+        ));
+
+        let res = range("2-4");
+        assert_eq!(res.status, 206);
+        assert_eq!(res.text(), "234");
+
+        let res = range("5-2");
+        assert_eq!(res.status, 200);
+        assert_eq!(res.text(), "0123456789");
+
+        let res = range("20-30");
+        assert_eq!(res.status, 416);
+        assert_eq!(res.header("Content-Range"), Some("bytes */10"));
+        assert_eq!(res.text(), "error 416 The requested range is not satisfiable");
+    }
+
+    #[test]
+    fn partial_content_is_cached_like_the_whole() {
+        let dir = TempDir::new();
+        dir.file("data.bin", "0123456789");
+        let mut config = config_for(&dir);
+        config.cache.default = Some(60);
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+
+        let whole = get(address, "/data.bin");
+        assert_eq!(whole.header("Cache-Control"), Some("public, max-age=60"));
+        let part = request(
+            address,
+            "GET /data.bin HTTP/1.1\r\nHost: localhost\r\nRange: bytes=0-1\r\n"
+        );
+        assert_eq!(part.status, 206);
+        assert_eq!(part.header("Cache-Control"), Some("public, max-age=60"));
+    }
+
+    #[test]
+    fn json_listings_are_compressed_from_their_own_threshold() {
+        let dir = TempDir::new();
+        for i in 0..40 {
+            dir.file(&format!("docs/file-{}.txt", i), "");
+        }
+        let config = Config {
+            json_listing: true,
+            gzip: true,
+            compression: vec![
+                (String::from("application/json"), 256),
+                (String::from("text/html"), 4096),
+            ].into_iter().collect(),
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+        let gzipped = |path: &str| request(address, &format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n",
+            path
+        ));
+
+        let res = gzipped("/docs/?format=json");
+        assert_eq!(res.status, 200);
+        assert_eq!(res.header("Content-Encoding"), Some("gzip"));
+        let mut listing = String::new();
+        GzDecoder::new(&res.body[..]).read_to_string(&mut listing).unwrap();
+        assert!(listing.contains("file-39.txt"));
+
+        // The page is smaller than the threshold for HTML
+        let res = gzipped("/docs/");
+        assert_eq!(res.status, 200);
+        assert_eq!(res.header("Content-Encoding"), None);
+        assert!(res.text().contains("file-39.txt"));
+        assert!(res.header("Vary").unwrap().contains("Accept-Encoding"));
+    }
+
+    #[test]
+    fn compressed_responses_are_logged_with_their_encoding() {
+        let dir = TempDir::new();
+        dir.file("docs/a.txt", "a");
+        let config = Config {
+            gzip: true,
+            gzip_min_size: 0,
+            ..config_for(&dir)
+        };
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let archivist = {
+            let events = events.clone();
+            Archivist::summon(&config, Arc::new(TestRenderer))
+                .on_access(move |e| events.lock().unwrap().push(e.encoding.clone()))
+        };
+        let address = serve(archivist);
+
+        let res = request(
+            address,
+            "GET /docs/ HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n"
+        );
+        assert_eq!(res.header("Content-Encoding"), Some("gzip"));
+        get(address, "/docs/");
+        assert_eq!(*events.lock().unwrap(), vec![Some(String::from("gzip")), None]);
+    }
+
+    #[test]
+    fn compressed_bodies_are_kept_by_their_entity_tag() {
+        let dir = TempDir::new();
+        let path = dir.file("notes.md", &"first ".repeat(10));
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let config = Config {
+            markdown: vec![OsString::from("md")].into_iter().collect(),
+            markdown_negotiation: true,
+            gzip: true,
+            gzip_min_size: 0,
+            compression_cache_size: 4,
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+        let fetch = |encoding: &str| {
+            let res = request(address, &format!(
+                "GET /notes.md HTTP/1.1\r\nHost: localhost\r\nAccept: text/markdown\r\n{}",
+                encoding
+            ));
+            let mut body = String::new();
+            if res.header("Content-Encoding") == Some("gzip") {
+                GzDecoder::new(&res.body[..]).read_to_string(&mut body).unwrap();
+            } else {
+                body = res.text();
+            }
+            (res.header("ETag").unwrap().to_owned(), body)
+        };
+        let gzipped = "Accept-Encoding: gzip\r\n";
+
+        let (etag, body) = fetch(gzipped);
+        assert_eq!(body, "first ".repeat(10));
+
+        // Content changed behind the same entity tag is still sent as it was compressed
+        let rewrite = |content: &str, modified: SystemTime| {
+            let file = File::create(&path).unwrap();
+            (&file).write_all(content.as_bytes()).unwrap();
+            file.set_modified(modified).unwrap();
+        };
+        rewrite(&"other ".repeat(10), modified);
+        assert_eq!(fetch(gzipped), (etag.clone(), "first ".repeat(10)));
+        assert_eq!(fetch("").1, "other ".repeat(10));
+
+        // A new version is compressed anew
+        rewrite(&"other ".repeat(10), modified + Duration::from_secs(1));
+        let (new_etag, body) = fetch(gzipped);
+        assert_ne!(new_etag, etag);
+        assert_eq!(body, "other ".repeat(10));
+    }
+
+    #[test]
+    fn markdown_is_negotiated_by_type_and_coding_with_an_entity_tag_for_each() {
+        let dir = TempDir::new();
+        dir.file("notes.md", &"# Notes\n".repeat(10));
+        let config = Config {
+            markdown: vec![OsString::from("md")].into_iter().collect(),
+            markdown_negotiation: true,
+            gzip: true,
+            gzip_min_size: 16,
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+        let fetch = |accept: &str, encoding: &str, if_none_match: &str| request(address, &format!(
+            "GET /notes.md HTTP/1.1\r\nHost: localhost\r\nAccept: {}\r\n{}{}",
+            accept,
+            if encoding.is_empty() { String::new() } else {
+                format!("Accept-Encoding: {}\r\n", encoding)
+            },
+            if if_none_match.is_empty() { String::new() } else {
+                format!("If-None-Match: {}\r\n", if_none_match)
+            }
+        ));
+
+        let mut etags = Vec::new();
+        for &(accept, content_type) in &[
+            ("text/html", "text/html"),
+            ("text/markdown", "text/markdown"),
+            ("text/plain", "text/plain"),
+        ] {
+            for &encoding in &["gzip", ""] {
+                let res = fetch(accept, encoding, "");
+                assert_eq!(res.status, 200);
+                assert!(res.header("Content-Type").unwrap().starts_with(content_type));
+                assert_eq!(res.header("Vary"), Some("Accept, Accept-Encoding"));
+                let mut body = String::new();
+                if encoding == "gzip" {
+                    assert_eq!(res.header("Content-Encoding"), Some("gzip"));
+                    GzDecoder::new(&res.body[..]).read_to_string(&mut body).unwrap();
+                } else {
+                    assert_eq!(res.header("Content-Encoding"), None);
+                    body = res.text();
+                }
+                assert!(body.contains("Notes"));
+                etags.push((accept, encoding, res.header("ETag").unwrap().to_owned()));
+            }
+        }
+        for (i, (_, _, etag)) in etags.iter().enumerate() {
+            assert!(etags[i + 1..].iter().all(|(_, _, other)| other != etag));
+        }
+
+        // Each entity tag only matches the representation it was given to
+        for &(accept, encoding, ref etag) in &etags {
+            for &(other_accept, other_encoding, _) in &etags {
+                let res = fetch(other_accept, other_encoding, etag);
+                if (accept, encoding) == (other_accept, other_encoding) {
+                    assert_eq!(res.status, 304);
+                    assert_eq!(res.header("ETag"), Some(&etag[..]));
+                    assert_eq!(res.header("Vary"), Some("Accept, Accept-Encoding"));
+                } else {
+                    assert_eq!(res.status, 200);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_root_is_listed_however_its_path_is_spelt() {
+        let dir = TempDir::new();
+        dir.file("a.txt", "a");
+        let address = serve(Archivist::summon(&config_for(&dir), Arc::new(TestRenderer)));
+
+        for res in &[
+            get(address, "/"),
+            get(address, "/?order="),
+            // An absolute url without a path
+            request(address, "GET http://localhost HTTP/1.1\r\nHost: localhost\r\n"),
+        ] {
+            assert_eq!(res.status, 200);
+            assert_eq!(res.text(), "dir /\na.txt\n");
+        }
+    }
+
+    #[test]
+    fn backslashes_and_slashes_can_be_mixed() {
+        let dir = TempDir::new();
+        dir.file("a/b/c.txt", "c");
+        let config = Config {
+            normalize_backslashes: true,
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+
+        for path in &["/a%5Cb/c.txt", "/a/b%5Cc.txt", "/a%5C%5Cb%5Cc.txt"] {
+            let res = get(address, path);
+            assert_eq!(res.status, 200, "{}", path);
+            assert_eq!(res.text(), "verbatim /a/b/c.txt\nc");
+        }
+        // A trailing backslash stands for a trailing slash
+        assert_eq!(get(address, "/a/b%5C").text(), "dir /a/b\nc.txt\n");
+        assert_eq!(get(address, "/a%5Cb/c.txt%5C").status, 404);
+
+        let address = serve(Archivist::summon(&config_for(&dir), Arc::new(TestRenderer)));
+        assert_eq!(get(address, "/a%5Cb/c.txt").status, 404);
+    }
+
+    #[test]
+    fn files_removed_while_they_are_served_are_not_found() {
+        let dir = TempDir::new();
+        let address = serve(Archivist::summon(&config_for(&dir), Arc::new(TestRenderer)));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // The file and the directory keep appearing and disappearing
+        // So some requests find them gone after they are looked up
+        let churn = {
+            let stop = stop.clone();
+            let file = dir.path().join("data.bin");
+            let sub = dir.path().join("sub");
+            thread::spawn(move || while !stop.load(Ordering::SeqCst) {
+                let _ = fs::write(&file, "0123456789");
+                let _ = fs::create_dir(&sub);
+                let _ = fs::remove_file(&file);
+                let _ = fs::remove_dir(&sub);
+            })
+        };
+        for _ in 0..200 {
+            for path in &["/data.bin", "/sub/"] {
+                let status = get(address, path).status;
+                assert!(status == 200 || status == 404, "{} {}", path, status);
+            }
+        }
+        stop.store(true, Ordering::SeqCst);
+        churn.join().unwrap();
+    }
+
+    #[test]
+    fn requests_without_a_host_are_redirected_to_the_default_host() {
+        let dir = TempDir::new();
+        dir.file("sub/a.txt", "a");
+        let config = Config {
+            trailing_slash_redirects: true,
+            default_host: Some(String::from("archive.example:8080")),
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+        let res = request(address, "GET /sub HTTP/1.0\r\n");
+        assert_eq!(res.status, 301);
+        assert_eq!(res.header("Location"), Some("http://archive.example:8080/sub/"));
+
+        // Without a host to redirect to, the client is not told where the directory is
+        let config = Config {
+            trailing_slash_redirects: true,
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+        assert_eq!(request(address, "GET /sub HTTP/1.0\r\n").status, 404);
+        let res = request(address, "GET /sub/a.txt HTTP/1.0\r\n");
+        assert_eq!(res.text(), "verbatim /sub/a.txt\na");
+    }
+
+    #[test]
+    fn head_is_answered_like_get() {
+        let dir = TempDir::new();
+        dir.file("sub/data.bin", "0123456789");
+        let config = Config {
+            listing_validators: true,
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+        let send = |method: &str, path: &str, if_none_match: Option<&str>| request(
+            address,
+            &format!(
+                "{} {} HTTP/1.1\r\nHost: localhost\r\n{}",
+                method,
+                path,
+                if_none_match.map(|t| format!("If-None-Match: {}\r\n", t)).unwrap_or_default()
+            )
+        );
+
+        for path in &["/sub/data.bin", "/sub/"] {
+            let etag = send("GET", path, None).header("ETag").unwrap().to_owned();
+            for &(if_none_match, status) in &[
+                (None, 200),
+                (Some(&etag[..]), 304),
+                (Some("W/\"other\""), 200),
+            ] {
+                let get = send("GET", path, if_none_match);
+                let head = send("HEAD", path, if_none_match);
+                assert_eq!(get.status, status, "{}", path);
+                assert_eq!(head.status, status, "{}", path);
+                for name in &["ETag", "Last-Modified"] {
+                    assert_eq!(get.header(name), head.header(name), "{} {}", path, name);
+                }
+                assert_eq!(get.header("ETag"), Some(&etag[..]));
+                assert!(head.body.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn clean_urls_tell_a_file_from_a_directory_of_the_same_name() {
+        let dir = TempDir::new();
+        dir.file("guide.txt", "the guide");
+        dir.file("guide/part-1.txt", "part 1");
+        let config = Config {
+            extensionless_urls: true,
+            extensionless_extensions: vec![String::from("txt")],
+            ..config_for(&dir)
+        };
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+
+        let res = get(address, "/guide");
+        assert_eq!(res.status, 200);
+        assert_eq!(res.text(), "verbatim /guide\nthe guide");
+        let res = get(address, "/guide/");
+        assert_eq!(res.status, 200);
+        assert_eq!(res.text(), "dir /guide\npart-1.txt\n");
+        assert_eq!(get(address, "/guide/part-1").text(), "verbatim /guide/part-1\npart 1");
+    }
+
+    #[test]
+    fn the_same_file_is_served_by_the_rule_of_its_directory() {
+        let dir = TempDir::new();
+        dir.file("shown/notes.txt", "same");
+        dir.file("downloads/notes.txt", "same");
+        let mut config = config_for(&dir);
+        let mut shown = rule("shown", &[]);
+        shown.force_verbatim.insert(OsString::from("txt"));
+        let mut downloads = rule("downloads", &[]);
+        downloads.force_raw.insert(OsString::from("txt"));
+        config.rules = vec![shown, downloads];
+        let address = serve(Archivist::summon(&config, Arc::new(TestRenderer)));
+
+        let res = get(address, "/shown/notes.txt");
+        assert_eq!(res.status, 200);
+        assert_eq!(res.text(), "verbatim /shown/notes.txt\nsame");
+        let res = get(address, "/downloads/notes.txt");
+        assert_eq!(res.status, 200);
+        assert_eq!(res.text(), "same");
+        assert_eq!(res.header("Accept-Ranges"), Some("bytes"));
+    }
+
+    #[test]
+    fn segments_that_are_not_utf8_are_not_found() {
+        let dir = TempDir::new();
+        dir.file("a.txt", "a");
+        let address = serve(Archivist::summon(&config_for(&dir), Arc::new(TestRenderer)));
+
+        for path in &["/%FF.txt", "/%C3%28/a.txt", "/a%E2%82.txt"] {
+            let res = get(address, path);
+            assert_eq!(res.status, 404, "{}", path);
+            assert_eq!(res.text(), "error 404 The requested archive is not found");
+        }
+        // The archivist keeps answering afterwards
+        assert_eq!(get(address, "/a.txt").status, 200);
+    }
+}
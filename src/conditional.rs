@@ -0,0 +1,101 @@
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use iron::prelude::*;
+use iron::headers::{EntityTag, ETag, HttpDate, IfModifiedSince, IfNoneMatch, LastModified};
+use iron::status;
+
+use std::time::SystemTime;
+
+/// The validators identifying a version of the content served
+///
+/// The time of modification is only kept to the second, as precise as HTTP dates are.
+///
+#[derive(Debug, Clone)]
+pub struct Validators {
+    pub etag: EntityTag,
+    pub last_modified: DateTime<Utc>,
+}
+
+impl Validators {
+    /// Builds the validators of a version of the content
+    ///
+    /// # Arguments
+    /// * `etag`     - The entity tag of the version
+    /// * `modified` - The time the version was last modified
+    ///
+    pub fn new(etag: EntityTag, modified: SystemTime) -> Validators {
+        let modified = DateTime::<Utc>::from(modified);
+        Validators {
+            etag,
+            last_modified: modified.with_nanosecond(0).unwrap_or(modified),
+        }
+    }
+
+    /// Checks if the client already has this version of the content
+    ///
+    /// `If-None-Match` takes precedence over `If-Modified-Since`, as entity tags are more precise
+    /// than dates.
+    ///
+    /// # Arguments
+    /// * `req` - The request, which may be conditional
+    ///
+    pub fn is_fresh(&self, req: &Request) -> bool {
+        if let Some(if_none_match) = req.headers.get::<IfNoneMatch>() {
+            return match *if_none_match {
+                IfNoneMatch::Any => true,
+                IfNoneMatch::Items(ref tags) => tags.iter().any(|t| t.weak_eq(&self.etag)),
+            };
+        }
+        if let Some(IfModifiedSince(since)) = req.headers.get::<IfModifiedSince>() {
+            let mut since = since.0;
+            if is_rfc850_date(req) {
+                since.tm_year = recent_year(since.tm_year % 100) - 1900;
+            }
+            return since.to_timespec().sec >= self.last_modified.timestamp();
+        }
+        false
+    }
+
+    /// Adds the `ETag` and `Last-Modified` headers to the response
+    ///
+    /// # Arguments
+    /// * `res` - The response serving this version of the content
+    ///
+    pub fn apply(&self, res: &mut Response) {
+        res.headers.set(ETag(self.etag.clone()));
+        if let Ok(date) = http_date(&self.last_modified).parse::<HttpDate>() {
+            res.headers.set(LastModified(date));
+        }
+    }
+
+    /// Builds the `304 Not Modified` response telling the client to use its own copy
+    pub fn not_modified(&self) -> Response {
+        let mut res = Response::with(status::NotModified);
+        self.apply(&mut res);
+        res
+    }
+}
+
+// Whether or not the If-Modified-Since header is in the obsolete RFC 850 format,
+// such as `Sunday, 06-Nov-94 08:49:37 GMT'
+fn is_rfc850_date(req: &Request) -> bool {
+    req.headers.get_raw("If-Modified-Since")
+        .and_then(|v| v.first())
+        .is_some_and(|v| v.contains(&b'-'))
+}
+
+// The most recent year ending in the two digits, not more than 50 years in the future
+// This is how two-digit years are to be read, according to RFC 7231
+fn recent_year(two_digits: i32) -> i32 {
+    let this_year = Utc::now().year();
+    let year = this_year - this_year % 100 + two_digits;
+    if year > this_year + 50 {
+        year - 100
+    } else {
+        year
+    }
+}
+
+// Formats the time as an HTTP date, such as `Sun, 06 Nov 1994 08:49:37 GMT'
+fn http_date(time: &DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
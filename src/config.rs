@@ -1,16 +1,20 @@
 use toml;
 
-use iron::mime::{TopLevel, Mime};
-use mime_guess::get_mime_type;
+use iron::mime::{Mime, SubLevel, TopLevel};
+use mime_guess::{get_mime_type_opt, octet_stream};
+use url::Url;
 
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::io;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Component;
 use std::path::Path;
+use std::path::PathBuf;
 use std::ffi::OsStr;
 use std::ffi::OsString;
+use std::time::{Duration, SystemTime};
 
 /// How a file should be served to the user.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -20,6 +24,9 @@ pub enum AccessMethod {
     /// Return the textual content without modification
     Verbatim,
     /// Return the raw file
+    ///
+    /// Only raw files take part in range requests. The content served by the other access
+    /// methods is rendered for each request, and is always served as a whole.
     Raw,
     /// Show the directory
     Dir,
@@ -27,17 +34,210 @@ pub enum AccessMethod {
 
 impl AccessMethod {
     pub fn is_file(&self) -> bool {
-        match self {
-            &AccessMethod::Dir => false,
-            _ => true,
-        }
+        !self.is_dir()
     }
 
     pub fn is_dir(&self) -> bool {
-        match self {
-            &AccessMethod::Dir => true,
-            _ => false,
+        *self == AccessMethod::Dir
+    }
+}
+
+/// How long the served content may be cached by clients and proxies
+///
+/// The maximum ages are given in seconds. A maximum age of zero means that the content must be
+/// revalidated on every request.
+///
+#[derive(Debug, Clone, Default)]
+pub struct CachePolicy {
+    /// The maximum age of files whose extensions are not listed in `extensions`
+    pub default: Option<u64>,
+    /// The maximum age of directory listings
+    ///
+    /// Directory listings are never cached if this is not set.
+    pub listing: Option<u64>,
+    /// The maximum ages of files with specific extensions
+    pub extensions: BTreeMap<OsString, u64>,
+    /// The minimum length of the content hash in the names of immutable files
+    ///
+    /// A file is immutable if its name contains a part between two dots that consists of at
+    /// least this many hexadecimal digits, such as `app.9f2c41d0.js`. Immutable files may be
+    /// cached forever. No file is considered immutable if this is not set.
+    pub immutable_hash_length: Option<usize>,
+}
+
+impl CachePolicy {
+    /// Returns the maximum age of the file at the specified path
+    /// Returns None if no caching policy applies to the file
+    ///
+    /// # Arguments
+    /// * `path`   - The path to the specified file
+    /// * `method` - The access method by which the file is served
+    ///
+    pub fn max_age_for<P: AsRef<Path>>(&self, path: &P, method: AccessMethod)
+            -> Option<u64> {
+        if method.is_dir() {
+            return Some(self.listing.unwrap_or(0));
         }
+        path.as_ref()
+            .extension()
+            .and_then(|ext| self.extensions.get(ext))
+            .cloned()
+            .or(self.default)
+    }
+
+    /// Checks if the file at the specified path is named with a content hash
+    ///
+    /// # Arguments
+    /// * `path` - The path to the specified file
+    ///
+    pub fn is_immutable<P: AsRef<Path>>(&self, path: &P) -> bool {
+        let min_length = match self.immutable_hash_length {
+            Some(l) => l,
+            None => return false,
+        };
+        let file_name = match path.as_ref().file_name().and_then(OsStr::to_str) {
+            Some(s) => s,
+            None => return false,
+        };
+        // The hash is neither the stem nor the extension of the file name
+        let parts : Vec<&str> = file_name.split('.').collect();
+        parts.len() > 2 && parts[1..parts.len() - 1].iter().any(|p| {
+            p.len() >= min_length && p.chars().all(|c| c.is_ascii_hexdigit())
+        })
+    }
+}
+
+/// How entries whose names are not valid UTF-8 are listed
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NonUtf8Names {
+    /// Leave the entries out of the listing
+    Skip,
+    /// List the entries with invalid sequences in their names replaced
+    Lossy,
+    /// Fail the listing with an error
+    Error,
+}
+
+/// How precisely the modification times of entries are shown in listings
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModifiedPrecision {
+    /// Show the time to the nanosecond
+    Full,
+    /// Show only the day
+    Date,
+    /// Do not show the time at all
+    ///
+    /// Listings cannot be sorted chronologically either, as the order would reveal the times.
+    Hidden,
+}
+
+/// How the IDs of requests not carrying one are generated
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestIdScheme {
+    /// A random 128-bit number, written in hex
+    Random,
+    /// The number of requests handled before, starting from 1
+    Counter,
+}
+
+/// Which of the urls of a file requested without its extension is canonical
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UrlForm {
+    /// The url without the extension, such as `/docs/getting-started`
+    Clean,
+    /// The url of the file itself, such as `/docs/getting-started.md`
+    Extension,
+}
+
+/// How the patterns in `blocked_user_agents` are matched
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PatternMatching {
+    /// The pattern matches if it occurs anywhere in the text
+    Substring,
+    /// The pattern matches the whole text, with `*` matching any run of characters and `?`
+    /// matching any single character
+    Wildcard,
+}
+
+/// The format of the sidecar files holding metadata about other files
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SidecarFormat {
+    /// A TOML table, such as `weight = 10`
+    Toml,
+    /// A JSON object, such as `{"weight": 10}`
+    Json,
+}
+
+/// How directory listings are split into pages
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pagination {
+    /// Pages are numbered, and asked for using `?page=N`
+    Offset,
+    /// Each page ends with a cursor, and the next page is asked for using `?cursor=...`
+    ///
+    /// Unlike numbered pages, a cursor keeps its place when entries are added to or removed from
+    /// the directory. Listings paginated by cursor are sorted by name unless another order is
+    /// asked for.
+    Cursor,
+}
+
+/// Access rules scoped to a directory
+///
+/// A rule applies to the files in its directory and all of its subdirectories, unless a rule for
+/// a subdirectory exists. Files allowed by a rule are served even if they are blocked, or begin
+/// with a `.`. Files blocked by a rule are never served. Other files are allowed or blocked
+/// according to the rest of the configuration.
+///
+#[derive(Debug, Clone)]
+pub struct Rule {
+    /// The path to the directory, relative to the served root
+    pub path: PathBuf,
+    /// The set of file names that will be allowed to be served
+    pub allow: BTreeSet<OsString>,
+    /// The set of file names that will be blocked from access
+    pub block: BTreeSet<OsString>,
+    /// The extensions of files that are always served raw
+    ///
+    /// This takes precedence over `force_raw` and `force_verbatim` in the configuration.
+    pub force_raw: BTreeSet<OsString>,
+    /// The extensions of files that are always served verbatim
+    ///
+    /// This takes precedence over `force_raw` and `force_verbatim` in the configuration.
+    pub force_verbatim: BTreeSet<OsString>,
+}
+
+/// A url serving the newest file in its directory, such as `releases/latest`
+///
+/// The newest file is the one modified last. Files still being written are not considered. An
+/// alias never shadows a file or directory that can be served at the same path.
+///
+#[derive(Debug, Clone)]
+pub struct LatestAlias {
+    /// The path to the alias, relative to the served root
+    ///
+    /// The newest file is looked for in the directory containing the alias.
+    pub path: PathBuf,
+    /// The pattern the names of the files must match, such as `*.tar.gz`
+    ///
+    /// `*` matches any run of characters and `?` matches any single character.
+    pub pattern: Option<String>,
+}
+
+impl LatestAlias {
+    /// Checks if the file with the specified name can be served by the alias
+    ///
+    /// # Arguments
+    /// * `name` - The name of the file
+    ///
+    pub fn matches(&self, name: &str) -> bool {
+        self.pattern.as_ref().is_none_or(|p| wildcard_match(p, name))
     }
 }
 
@@ -55,14 +255,65 @@ impl AccessMethod {
 /// listen = "localhost:5000"
 /// 
 /// # If allow_all is on then all files in the served directory are served
-/// # otherwise only files whose extensions are on the `allow' list are served
+/// # otherwise only files whose extensions are on the `allowed_extensions' list are served
 /// allow_all = false
 /// 
 /// # Only files with these extensions are allowed
-/// allow = [ "rs", "txt", "md", "html", "css", "jpg", "png" ]
+/// allowed_extensions = [ "rs", "txt", "md", "html", "css", "jpg", "png" ]
+///
+/// # Files with names matching these patterns are allowed as well
+/// allowed_globs = [ "*.min.js", "report-*.csv" ]
+///
+/// # Files and directories with names matching these patterns are never served
+/// blocked_globs = [ "*.key", "*~" ]
 /// 
 /// # Files with these extensions will be rendered as Markdown script
 /// markdown = [ "md" ]
+///
+/// # Files with these names or extensions are served but not listed
+/// hide_in_listing = [ "map", ".DS_Store" ]
+///
+/// # Pass the absolute path of the served files to the renderer
+/// expose_fs_paths = false
+///
+/// # Compress listings and rendered pages for clients that accept gzip
+/// gzip = true
+///
+/// # How long, in seconds, the served content may be cached
+/// # Directory listings are not cached unless `listing' is set
+/// [cache]
+/// default = 60
+/// listing = 0
+///
+/// # Files named like `app.9f2c41d0.js' are cached forever
+/// immutable_hash_length = 8
+///
+/// [cache.extensions]
+/// css = 86400
+/// png = 86400
+///
+/// # Compress responses of these types once they are at least this many bytes
+/// # JSON listings of large directories are worth compressing even when pages are not
+/// [compression]
+/// "application/json" = 256
+/// "text/html" = 4096
+///
+/// # Arrays of tables come last, as every key after one belongs to it
+///
+/// # Rules scoped to a directory take precedence over the lists above
+/// [[rules]]
+/// path = "config"
+/// allow = [ ".env" ]
+///
+/// # Text files in `downloads' are downloaded rather than shown
+/// [[rules]]
+/// path = "downloads"
+/// force_raw = [ "txt" ]
+///
+/// # `releases/latest' serves the newest tarball in `releases'
+/// [[latest_aliases]]
+/// path = "releases/latest"
+/// pattern = "*.tar.gz"
 /// ```
 ///
 #[derive(Debug, Clone)]
@@ -81,6 +332,371 @@ pub struct Config {
     pub blocked_file_names: BTreeSet<OsString>,
     /// The set of file extensions that will be treated as Markdown files
     pub markdown: BTreeSet<OsString>,
+    /// Whether or not the absolute file system path should be passed to the renderer
+    pub expose_fs_paths: bool,
+    /// How long the served content may be cached
+    pub cache: CachePolicy,
+    /// The maximum length in bytes of the decoded request path
+    pub max_path_length: usize,
+    /// The maximum number of segments in the request path
+    pub max_path_segments: usize,
+    /// Whether or not the number of entries in each subdirectory should be counted in listings
+    pub count_children: bool,
+    /// The number of levels by which the headings in Markdown files are shifted down
+    ///
+    /// For example, an offset of 1 renders `#` as `<h2>`. Headings never go below `<h6>`.
+    pub markdown_heading_offset: u32,
+    /// The set of file names and extensions that will be left out of directory listings
+    ///
+    /// Unlike blocked files, these files can still be accessed directly.
+    pub hide_in_listing: BTreeSet<OsString>,
+    /// Whether or not clients may ask for the source of Markdown files using `Accept`
+    ///
+    /// If this is set, clients that prefer `text/markdown` or `text/plain` over `text/html`
+    /// are served the source of Markdown files instead of the rendered page. Each of these, and
+    /// each of them compressed with `gzip`, is given its own entity tag.
+    pub markdown_negotiation: bool,
+    /// Whether or not Markdown files that fail to render are rendered verbatim instead
+    pub markdown_fallback_raw: bool,
+    /// Whether or not directories can be downloaded as zip archives using `?download=zip`
+    pub zip_download: bool,
+    /// The maximum total size in bytes of the files included in a zip archive
+    ///
+    /// Larger directories are refused with a 403 Forbidden. Archives are never larger than 4 GiB,
+    /// whatever this is set to.
+    pub zip_max_size: u64,
+    /// The maximum depth of subdirectories visited by features walking a directory tree
+    pub max_depth: usize,
+    /// The HTTP status code returned when the requested archive is not found
+    pub not_found_status: u16,
+    /// Whether or not requests to urls with non-canonical percent-encoding are redirected
+    pub canonical_urls: bool,
+    /// Access rules scoped to directories
+    pub rules: Vec<Rule>,
+    /// Whether or not backslashes in request paths are treated as path separators
+    pub normalize_backslashes: bool,
+    /// The maximum number of threads inspecting the entries of a directory at once
+    ///
+    /// Raising this speeds up listings on file systems with high latency. The threads are started
+    /// along with the archivist, and kept for as long as it is.
+    pub stat_concurrency: usize,
+    /// How entries whose names are not valid UTF-8 are listed
+    pub non_utf8_names: NonUtf8Names,
+    /// The path answering health checks, such as `/__health`
+    ///
+    /// Requests to this path are answered with `200 OK` if the served directory can be read, and
+    /// with `503 Service Unavailable` otherwise.
+    pub health_path: Option<String>,
+    /// Whether or not directories can be served as RSS feeds using `?format=rss`
+    pub feeds: bool,
+    /// The Mime assumed for files whose extensions are not recognised
+    ///
+    /// Files with a textual Mime are served verbatim, and other files are served raw, with this
+    /// Mime as their `Content-Type`.
+    pub default_mime: Mime,
+    /// The file names that are listed first in directory listings, in this order
+    pub pinned: Vec<OsString>,
+    /// Whether or not successful responses carry a `Link` header with `rel="canonical"`
+    ///
+    /// The canonical url is the requested url without its query.
+    pub canonical_link: bool,
+    /// Whether or not files served verbatim are rendered line by line
+    ///
+    /// See `Renderer::render_verbatim_lines`.
+    pub verbatim_line_numbers: bool,
+    /// The query parameters that are passed through to the renderer, such as `theme`
+    ///
+    /// See `RenderContext::params`.
+    pub passthrough_params: BTreeSet<String>,
+    /// The maximum number of entries on a page of a directory listing
+    ///
+    /// Listings are not split into pages if this is `None`. Clients can ask for another number
+    /// using `?per_page=N`, up to `max_page_size`.
+    pub page_size: Option<usize>,
+    /// How directory listings are split into pages
+    pub pagination: Pagination,
+    /// The maximum number of parameters in the query of a request
+    ///
+    /// Requests with more parameters are rejected with `400 Bad Request`.
+    pub max_query_params: usize,
+    /// The number of levels of subdirectories shown in directory listings
+    ///
+    /// A depth of one lists only the entries in the directory itself.
+    /// See `Renderer::render_dir_tree`.
+    pub listing_tree_depth: usize,
+    /// The maximum number of entries below the directory being listed shown in a tree listing
+    pub listing_tree_max_entries: usize,
+    /// The host, such as `example.com:8080`, used in redirects for requests without `Host`
+    ///
+    /// Redirects and links needing the host of the server are left out for such requests if this
+    /// is `None`.
+    pub default_host: Option<String>,
+    /// Whether or not directories can be served as JSON using `?format=json`
+    ///
+    /// Clients preferring `application/json` to `text/html` in their `Accept` header are also
+    /// served JSON, and listings then carry `Vary: Accept`. The JSON follows a versioned schema,
+    /// and the latest version is served unless another is asked for using `?schema_version=N`.
+    pub json_listing: bool,
+    /// How precisely the modification times of entries are shown in listings
+    pub modified_precision: ModifiedPrecision,
+    /// Whether or not files that cannot be found are looked for in other languages
+    ///
+    /// The variant in the language preferred in the `Accept-Language` header is served, or the
+    /// variant in `default_language` if none is preferred.
+    pub language_variants: bool,
+    /// How the variants of a file in other languages are named
+    ///
+    /// `{name}` is replaced by the name of the file without its extension, and `{lang}` stands
+    /// for the language tag. With the default `{name}.{lang}`, `page.md` has the variants
+    /// `page.en.md` and `page.fr.md`.
+    pub language_pattern: String,
+    /// The language served if the client has no preference among the variants of a file
+    pub default_language: Option<String>,
+    /// The name of the file listing the entries of its directory in the order they are shown
+    ///
+    /// The file lists one name per line. Entries not listed follow in the order of their names.
+    /// The file is only used if no order is asked for, and if listings are not paginated by
+    /// cursor. It is never shown in listings.
+    pub order_file: Option<String>,
+    /// Whether or not directory listings carry `ETag` and `Last-Modified` headers
+    ///
+    /// The headers are derived from the time the directory was last modified, along with the
+    /// query. Conditional requests are then answered with `304 Not Modified` if the directory
+    /// has not changed. Note that a directory is not modified when the content of its entries
+    /// changes, so listings showing the modification times of the entries may become stale,
+    /// unless `listing_modified_from_entries` is turned on.
+    pub listing_validators: bool,
+    /// Whether or not the root directory is shown as its rendered `README.md`
+    ///
+    /// See `Renderer::render_readme`. Other directories are listed as usual, and the root is
+    /// still listed if the README cannot be served as Markdown. This takes precedence over
+    /// `listing_tree_depth` for the root, but not over archives, feeds or JSON asked for by the
+    /// query.
+    pub root_readme: bool,
+    /// The path, such as `docs/`, that clients requesting the root directory are redirected to
+    ///
+    /// The path is relative to the root. Paths to directories must end with a slash.
+    pub root_redirect: Option<String>,
+    /// The charset stated for text files served raw, such as `utf-8`
+    ///
+    /// Rendered pages are always stated to be UTF-8.
+    pub charset: String,
+    /// The directories, relative to the root, that are downloaded as zip archives when requested
+    ///
+    /// Their subdirectories are downloaded likewise. These directories are never listed, even if
+    /// `zip_download` is turned off.
+    pub download_dirs: Vec<PathBuf>,
+    /// Whether or not the extensions of files are left out of their names in listings
+    ///
+    /// See `Entry::display_name`. Links still point to the files by their full names.
+    pub strip_extensions_in_listing: bool,
+    /// The extensions left out of names in listings, such as `md`
+    ///
+    /// If this is empty, all extensions are left out.
+    pub stripped_extensions: BTreeSet<OsString>,
+    /// Whether or not files can be requested without their extensions
+    ///
+    /// If nothing is found at a path without an extension, each of `extensionless_extensions` is
+    /// tried in order, so that `/docs/getting-started` serves `/docs/getting-started.md`.
+    ///
+    /// If there is also a directory at the path, it is served for the path with a trailing slash,
+    /// and the file is served for the path without one. A file at the path itself is always served
+    /// in place of both.
+    pub extensionless_urls: bool,
+    /// The extensions tried for paths without extensions, such as `md`
+    pub extensionless_extensions: Vec<String>,
+    /// Whether or not errors are answered in JSON to clients preferring it in `Accept`
+    ///
+    /// The JSON is of the form `{"error": 404, "message": "...", "path": "..."}`.
+    pub json_errors: bool,
+    /// The header carrying the ID of a request for tracing, such as `X-Request-Id`
+    ///
+    /// The ID given by the client is sent back in the same header of the response, and is passed
+    /// to the access log. Requests without an ID are given one. IDs are not used if this is `None`.
+    pub request_id_header: Option<String>,
+    /// How the IDs of requests not carrying one are generated
+    pub request_id_scheme: RequestIdScheme,
+    /// The largest rendered page that is sent, in bytes
+    ///
+    /// Listings, Markdown and verbatim pages larger than this are answered with `500` instead.
+    /// Files served raw are not limited. Pages are not limited if this is `None`.
+    pub max_response_size: Option<usize>,
+    /// The extensions of files that are always served raw, such as `csv`
+    ///
+    /// This overrides the choice made from the Mime of the file.
+    pub force_raw: BTreeSet<OsString>,
+    /// The extensions of files that are always served verbatim, such as `svg`
+    ///
+    /// This overrides the choice made from the Mime of the file.
+    pub force_verbatim: BTreeSet<OsString>,
+    /// Whether or not files found through `extensionless_urls` are redirected to one canonical url
+    ///
+    /// With `canonical_url_form` set to `extension`, `/docs/getting-started` is redirected to
+    /// `/docs/getting-started.md`. With it set to `clean`, `/docs/getting-started.md` is redirected
+    /// to `/docs/getting-started`, as long as that url finds the same file. The query is kept.
+    pub canonicalize_clean_urls: bool,
+    /// Which url of a file requested without its extension is canonical
+    pub canonical_url_form: UrlForm,
+    /// The policy sent in the `Content-Security-Policy` header of rendered pages
+    ///
+    /// Each `{nonce}` in the policy, such as in `script-src 'nonce-{nonce}'`, is replaced by a
+    /// random nonce that is different for every response. The nonce is passed to the renderer in
+    /// `RenderContext::csp_nonce`. The header is not sent if this is `None`.
+    pub content_security_policy: Option<String>,
+    /// Whether or not JSON listings are indented
+    ///
+    /// This can be overridden using `?pretty` or `?pretty=false`.
+    pub json_pretty: bool,
+    /// The patterns of `User-Agent` headers whose requests are refused, such as `BadBot`
+    pub blocked_user_agents: Vec<String>,
+    /// How the patterns in `blocked_user_agents` are matched
+    pub user_agent_matching: PatternMatching,
+    /// The HTTP status code returned to blocked user agents, such as `403` or `429`
+    pub blocked_user_agent_status: u16,
+    /// Whether or not requests without a `User-Agent` header are refused as well
+    pub block_missing_user_agent: bool,
+    /// The HTML added to the end of every rendered page, such as a footer
+    ///
+    /// The HTML is added before `</body>` if the page has one. Files served raw and JSON are left
+    /// as they are.
+    pub footer: Option<String>,
+    /// How long after its last modification a file is considered completely written, in milliseconds
+    ///
+    /// Files modified more recently are left out of listings, feeds and zip archives, and are
+    /// answered with `503` and a `Retry-After` header. Files are served as soon as they are
+    /// modified if this is `None`.
+    pub ignore_recent_writes_ms: Option<u64>,
+    /// The extension of the sidecar files holding metadata about other files, such as `meta`
+    ///
+    /// The sidecar of `photo.jpg` is `photo.jpg.meta`. Sidecars are left out of listings. Listings
+    /// not asked to be sorted otherwise, and without an order file, are sorted by the weights in
+    /// the sidecars of their entries, lightest first. Entries without weights follow by name.
+    /// Listings paginated by cursor are not sorted by weight.
+    pub sidecar_extension: Option<String>,
+    /// The format of the sidecar files
+    pub sidecar_format: SidecarFormat,
+    /// The field in the sidecar files holding the weight of the entry, such as `weight`
+    pub sidecar_weight_field: String,
+    /// Whether or not rendered Markdown pages carry `Link` headers preloading their local assets
+    ///
+    /// The assets are the images, style sheets and scripts referenced by relative urls in the
+    /// rendered page, that exist and are served raw.
+    pub markdown_preload: bool,
+    /// The number of requests each client can make in `rate_limit_window_secs`
+    ///
+    /// Clients making more requests are answered with `429` and a `Retry-After` header. Requests
+    /// are allowed steadily over the window rather than all at its start. Requests are not limited
+    /// if this is `None`.
+    pub rate_limit: Option<u32>,
+    /// The length of the window of `rate_limit` in seconds
+    pub rate_limit_window_secs: u64,
+    /// The header carrying the address of the client, such as `X-Forwarded-For`
+    ///
+    /// Only set this behind a proxy that sets the header, as clients can send any address in it.
+    /// The last address in the header is used, as it is the one added by the proxy. The address
+    /// of the connection is used if this is `None`, or if the header is missing.
+    pub client_ip_header: Option<String>,
+    /// The urls serving the newest files in their directories
+    pub latest_aliases: Vec<LatestAlias>,
+    /// Whether or not clients can ask for a range of the text of files served verbatim
+    ///
+    /// The range is served as `text/plain` with `206 Partial Content`, rather than as the
+    /// rendered page. It is narrowed to whole UTF-8 characters, so the bytes sent, as stated in
+    /// `Content-Range`, may be fewer than asked for. Only single ranges are served this way.
+    pub verbatim_ranges: bool,
+    /// Whether or not directories requested without a trailing slash, and files requested with one,
+    /// are redirected to the url spelt the right way
+    ///
+    /// They are not found if this is not set.
+    pub trailing_slash_redirects: bool,
+    /// Whether or not the permission bits of entries are passed to the renderer in listings
+    ///
+    /// See `Entry::mode`. Permissions are only known on Unix.
+    pub expose_permissions: bool,
+    /// The path serving a JSON description of the features enabled, such as
+    /// `/__archivist/capabilities`
+    ///
+    /// The description is not served if this is `None`.
+    pub capabilities_path: Option<String>,
+    /// Whether or not the validators of listings are derived from the newest of their entries
+    ///
+    /// The time a listing was last modified is then the latest of the times the directory and its
+    /// servable entries were modified. This costs a look at every entry for every conditional
+    /// request, but keeps listings fresh when the content of their entries changes.
+    pub listing_modified_from_entries: bool,
+    /// Whether or not rendered pages are compressed with gzip for clients that accept it
+    ///
+    /// Only listings and rendered or verbatim files are compressed, along with the sources of
+    /// Markdown files served in place of their rendered pages. Files served raw are sent as they
+    /// are.
+    pub gzip: bool,
+    /// The size in bytes below which rendered pages are not compressed
+    ///
+    /// Small pages gain little from compression, and may even grow. HTML pages and the sources of
+    /// Markdown files are compressed from this size unless `compression` says otherwise.
+    pub gzip_min_size: usize,
+    /// The sizes in bytes from which responses of specific content types are compressed, such as
+    /// `application/json`
+    ///
+    /// The content types are matched without their parameters. Responses of types that are not
+    /// listed, other than HTML and the sources of Markdown files, are never compressed.
+    pub compression: BTreeMap<String, usize>,
+    /// The number of compressed responses kept, so that the same content is not compressed again
+    ///
+    /// Only responses with an entity tag are kept, by the file or directory they were served from
+    /// and their entity tag. A kept response is sent for as long as the entity tag stays the same,
+    /// much as clients revalidating their copies are told to keep them. The responses kept least
+    /// recently used are forgotten first. No responses are kept if this is `0`.
+    pub compression_cache_size: usize,
+    /// Whether or not directories are listed before files
+    ///
+    /// The entries in each group stay in the order asked for, even in descending order. Entries
+    /// placed by an order file, or by the weights in their sidecars, are left where they are.
+    pub dirs_first: bool,
+    /// The names of the files served in place of the listing of the directory containing them
+    ///
+    /// The file found in a directory, such as `index.html`, is served as it would be if it were
+    /// asked for, according to its own access method. The directory is listed as usual if none of
+    /// the files can be served. If several of them are found, the one whose Mime the client
+    /// prefers in its `Accept` header is served, or the first listed if it prefers none of them.
+    /// The response then carries `Vary: Accept`.
+    pub index_files: Vec<String>,
+    /// The largest number of entries per page that clients can ask for using `?per_page=N`
+    ///
+    /// Clients can only ask for pages smaller than `page_size` if this is `None`. Larger numbers
+    /// are lowered to this one rather than refused.
+    pub max_page_size: Option<usize>,
+    /// The user name required to access the archive, along with `auth_password`
+    ///
+    /// Clients are asked to log in with HTTP basic authentication if both are set, and answered
+    /// with `401` until they do. Health checks are answered regardless. Basic authentication sends
+    /// the password as it is, so only serve the archive over HTTPS, such as behind a proxy.
+    pub auth_user: Option<String>,
+    /// The password required to access the archive, along with `auth_user`
+    pub auth_password: Option<String>,
+    /// The realm of the archive, shown by browsers asking for the user name and password
+    pub auth_realm: String,
+    /// The files served in place of the rendered error pages, by status code
+    ///
+    /// The files, such as `404.html`, are relative to the served root and sent as they are, as
+    /// HTML. The renderer renders the error page as usual if the file cannot be read.
+    pub error_pages: BTreeMap<u16, String>,
+    /// The Mimes of files with specific extensions, in place of the ones guessed
+    ///
+    /// The Mime decides whether files are served verbatim or raw, as guessed ones do, and is sent
+    /// with the files served raw.
+    pub mime_overrides: BTreeMap<OsString, Mime>,
+    /// The patterns the names of files must match to be allowed, such as `*.min.js`
+    ///
+    /// `*` matches any run of characters and `?` matches any single character. Files with names
+    /// matching any of the patterns are allowed as if their names were in `allowed_file_names`,
+    /// except that hidden files are not.
+    pub allowed_globs: Vec<String>,
+    /// The patterns the names of blocked files and directories match, such as `*.key` or `*~`
+    ///
+    /// Every part of the path is checked, so the files in matching directories are blocked too.
+    /// Blocking takes precedence over allowing, even by a scoped rule.
+    pub blocked_globs: Vec<String>,
 }
 
 impl Config {
@@ -96,9 +712,11 @@ impl Config {
         let mut file = File::open(path.as_ref())?;
         let mut content = String::new();
         file.read_to_string(&mut content)?;
-        toml::from_str::<RawConfig>(content.as_str())
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-            .map(Config::from)
+        let raw = toml::from_str::<RawConfig>(content.as_str())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        raw.validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Config::from(raw))
     }
 
     /// Returns the access method specified for the file at the specified path
@@ -122,19 +740,43 @@ impl Config {
             None => OsStr::new(""),
         };
 
+        // Only the part of the path inside the served directory is checked
+        let rel_path = path.strip_prefix(&self.root_dir).unwrap_or(path);
+        let mut parent = PathBuf::new();
+        // Whether or not the file is explicitly allowed by a scoped rule
+        let mut allowed_by_rule = false;
+
         // If the file name begins with a `.', and the file is not allowed
         // then do not allow
         // Note that these files are not allowed even if `allow-all' is set
-        for c in path.components() {
+        //
+        // The most specific rule scoped to the directory containing the file
+        // takes precedence over the global sets of allowed and blocked names
+        for c in rel_path.components() {
             match c {
                 Component::Normal(s) => {
+                    let (allowed, blocked) = match self.rule_for(&parent) {
+                        Some(r) => (r.allow.contains(s), r.block.contains(s)),
+                        None => (false, false),
+                    };
                     let s_str = s.to_str().unwrap_or("");
-                    if (s.len() > 0
-                            && s_str.starts_with(".")
-                            && !self.allowed_file_names.contains(s))
-                            || self.blocked_file_names.contains(s) {
+                    // Only Windows gives these names a special meaning
+                    if cfg!(windows) && is_reserved_name(s_str) {
+                        return Ok(None);
+                    }
+                    // Names matching a blocked pattern are blocked even if allowed
+                    if self.blocked_globs.iter().any(|g| wildcard_match(g, s_str)) {
+                        return Ok(None);
+                    }
+                    if blocked || !allowed
+                            && ((!s.is_empty()
+                                && s_str.starts_with(".")
+                                && !self.allowed_file_names.contains(s))
+                                || self.blocked_file_names.contains(s)) {
                         return Ok(None);
                     }
+                    allowed_by_rule = allowed;
+                    parent.push(s);
                 },
 
                 // Make sure that the path does not go up
@@ -160,6 +802,12 @@ impl Config {
             return Ok(Some(AccessMethod::Dir));
         }
 
+        // Whether or not the file name matches any of the allowed patterns
+        let allowed_by_glob = match file_name.to_str() {
+            Some(name) => self.allowed_globs.iter().any(|g| wildcard_match(g, name)),
+            None => false,
+        };
+
         // If we cannot get the extension, and the file is not explicitly allowed
         // then do not allow
         // Unless allow-all is set
@@ -167,6 +815,8 @@ impl Config {
             Some(ext) => ext,
             None => {
                 if self.allowed_file_names.contains(file_name)
+                        || allowed_by_glob
+                        || allowed_by_rule
                         || self.allow_all {
                     // If the file name is allowed but it does not contain an extension
                     // Then treat the file as plain text
@@ -181,7 +831,9 @@ impl Config {
         // then do not allow
         if !self.allow_all
                 && !self.allowed_extensions.contains(ext)
-                && !self.allowed_file_names.contains(file_name) {
+                && !self.allowed_file_names.contains(file_name)
+                && !allowed_by_glob
+                && !allowed_by_rule {
             return Ok(None);
         }
 
@@ -190,19 +842,190 @@ impl Config {
             return Ok(Some(AccessMethod::Markdown));
         }
 
-        // Otherwise guess the Mime of the file
+        // If the extension is configured to be served in a certain way then do so
+        // The most specific rule scoped to the directory containing the file
+        // takes precedence over the global configuration
+        let dir = rel_path.parent().unwrap_or(Path::new(""));
+        let (force_raw, force_verbatim) = match self.rule_for(&dir) {
+            Some(r) if r.force_raw.contains(ext) || r.force_verbatim.contains(ext) =>
+                (&r.force_raw, &r.force_verbatim),
+            _ => (&self.force_raw, &self.force_verbatim),
+        };
+        if force_raw.contains(ext) {
+            return Ok(Some(AccessMethod::Raw));
+        }
+        if force_verbatim.contains(ext) {
+            return Ok(Some(AccessMethod::Verbatim));
+        }
+
+        // Otherwise guess the Mime of the file, as it would be sent raw
         // If the file is text then access its textual content
         // Otherwise access the raw file
-        let ext_str = match ext.to_str() {
-            Some(s) => s,
-            None => return Ok(None),
-        };
-        let mime = get_mime_type(ext_str);
-        match mime {
+        if ext.to_str().is_none() {
+            return Ok(None);
+        }
+        match self.mime_for(&path) {
             Mime(TopLevel::Text, _, _) => Ok(Some(AccessMethod::Verbatim)),
             _ => Ok(Some(AccessMethod::Raw)),
         }
     }
+
+    /// Returns the Mime of the file at the specified path, judging by its extension
+    ///
+    /// The Mime in `mime_overrides` is used if there is one for the extension. Otherwise the Mime
+    /// is guessed, and files with unknown extensions are given `default_mime`.
+    ///
+    /// # Arguments
+    /// * `path` - The path to the specified file
+    ///
+    pub fn mime_for<P: AsRef<Path>>(&self, path: &P) -> Mime {
+        let ext = path.as_ref().extension();
+        ext.and_then(|ext| self.mime_overrides.get(ext).cloned())
+            .or_else(|| ext.and_then(|ext| ext.to_str()).and_then(get_mime_type_opt))
+            .unwrap_or_else(|| self.default_mime.clone())
+    }
+
+    /// Returns the size in bytes from which responses of the specified Mime are compressed
+    /// Returns None if they are never compressed
+    ///
+    /// # Arguments
+    /// * `mime` - The Mime of the response, whose parameters are ignored
+    ///
+    pub fn compression_threshold(&self, mime: &Mime) -> Option<usize> {
+        let Mime(ref top, ref sub, _) = *mime;
+        let essence = format!("{}/{}", top, sub).to_ascii_lowercase();
+        let default = match (top, sub) {
+            (TopLevel::Text, SubLevel::Html) | (TopLevel::Text, SubLevel::Plain) =>
+                Some(self.gzip_min_size),
+            (TopLevel::Text, SubLevel::Ext(ext)) if ext == "markdown" =>
+                Some(self.gzip_min_size),
+            _ => None,
+        };
+        self.compression.get(&essence).cloned().or(default)
+    }
+
+    /// Returns the most specific scoped rule applying to the files in a directory
+    /// Returns None if no rule applies
+    ///
+    /// # Arguments
+    /// * `dir` - The path to the directory, relative to the served root
+    ///
+    pub fn rule_for<P: AsRef<Path>>(&self, dir: &P) -> Option<&Rule> {
+        self.rules.iter()
+            .filter(|r| dir.as_ref().starts_with(&r.path))
+            .max_by_key(|r| r.path.components().count())
+    }
+
+    /// Checks if the directory at the specified path is downloaded instead of listed
+    ///
+    /// # Arguments
+    /// * `dir` - The path to the directory, relative to the served root
+    ///
+    pub fn is_download_dir<P: AsRef<Path>>(&self, dir: &P) -> bool {
+        self.download_dirs.iter().any(|d| dir.as_ref().starts_with(d))
+    }
+
+    /// Checks if the file at the specified path should be left out of directory listings
+    ///
+    /// # Arguments
+    /// * `path` - The path to the specified file
+    ///
+    pub fn is_hidden_in_listing<P: AsRef<Path>>(&self, path: &P) -> bool {
+        let path = path.as_ref();
+        path.file_name().is_some_and(|n| self.hide_in_listing.contains(n))
+            || path.extension().is_some_and(|e| self.hide_in_listing.contains(e))
+            || self.order_file.as_ref().is_some_and(|o| path.file_name() == Some(OsStr::new(o)))
+            || self.sidecar_extension.as_ref().is_some_and(|s| path.extension() == Some(OsStr::new(s)))
+    }
+
+    /// Returns how long until the file at the specified path is considered completely written
+    ///
+    /// Returns `None` if the file is ready to be served, or if it is a directory.
+    ///
+    /// # Arguments
+    /// * `path` - The path to the specified file
+    ///
+    pub fn time_until_written<P: AsRef<Path>>(&self, path: &P) -> Option<Duration> {
+        let window = Duration::from_millis(self.ignore_recent_writes_ms?);
+        let metadata = path.as_ref().metadata().ok()?;
+        if metadata.is_dir() {
+            return None;
+        }
+        // Files modified in the future are as good as being written
+        let age = SystemTime::now()
+            .duration_since(metadata.modified().ok()?)
+            .unwrap_or(Duration::from_secs(0));
+        if age < window {
+            Some(window - age)
+        } else {
+            None
+        }
+    }
+
+    /// Checks if requests from the specified user agent are blocked
+    ///
+    /// Patterns are matched ignoring case.
+    ///
+    /// # Arguments
+    /// * `user_agent` - The `User-Agent` header of the request, if it has one
+    ///
+    pub fn is_blocked_user_agent(&self, user_agent: Option<&str>) -> bool {
+        let user_agent = match user_agent {
+            Some(ua) if !ua.trim().is_empty() => ua.to_lowercase(),
+            _ => return self.block_missing_user_agent,
+        };
+        self.blocked_user_agents.iter().any(|pattern| {
+            let pattern = pattern.to_lowercase();
+            match self.user_agent_matching {
+                PatternMatching::Substring => user_agent.contains(&pattern),
+                PatternMatching::Wildcard => wildcard_match(&pattern, &user_agent),
+            }
+        })
+    }
+}
+
+// Checks if the pattern matches the whole of the text,
+// where `*' matches any run of characters and `?' matches any single character
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern : Vec<char> = pattern.chars().collect();
+    let text : Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*', if what follows it fails to match
+    let mut resume = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            resume = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = resume {
+            // Let the `*' match one more character
+            resume = Some((star, matched + 1));
+            p = star + 1;
+            t = matched + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Checks if the name has a special meaning to Windows
+//
+// These are device names such as `CON' and `NUL', even with an extension,
+// and names ending with a dot or a space
+fn is_reserved_name(name: &str) -> bool {
+    if name.ends_with('.') || name.ends_with(' ') {
+        return true;
+    }
+    let stem = name.split('.').next().unwrap_or("").trim_end().to_uppercase();
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => (stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.len() == 4
+            && stem[3..].chars().all(|c| ('1'..='9').contains(&c)),
+    }
 }
 
 impl Default for Config {
@@ -218,17 +1041,17 @@ impl From<RawConfig> for Config {
             listen:             raw.listen,
             allow_all:          raw.allow_all,
             allowed_extensions:
-                raw.allowed_extensions.unwrap_or(BTreeSet::new())
+                raw.allowed_extensions.unwrap_or_default()
                    .iter()
                    .map(OsString::from)
                    .collect(),
             allowed_file_names:
-                raw.allowed_file_names.unwrap_or(BTreeSet::new())
+                raw.allowed_file_names.unwrap_or_default()
                    .iter()
                    .map(OsString::from)
                    .collect(),
             blocked_file_names:
-                raw.blocked_file_names.unwrap_or(BTreeSet::new())
+                raw.blocked_file_names.unwrap_or_default()
                    .iter()
                    .map(OsString::from)
                    .collect(),
@@ -240,6 +1063,168 @@ impl From<RawConfig> for Config {
                 }).iter()
                   .map(OsString::from)
                   .collect(),
+            expose_fs_paths:    raw.expose_fs_paths.unwrap_or(false),
+            cache:              raw.cache.map(CachePolicy::from).unwrap_or_default(),
+            max_path_length:    raw.max_path_length.unwrap_or(4096),
+            max_path_segments:  raw.max_path_segments.unwrap_or(256),
+            count_children:     raw.count_children.unwrap_or(false),
+            markdown_heading_offset:
+                raw.markdown_heading_offset.unwrap_or(0),
+            hide_in_listing:
+                raw.hide_in_listing.unwrap_or_default()
+                   .iter()
+                   .map(OsString::from)
+                   .collect(),
+            markdown_negotiation:
+                raw.markdown_negotiation.unwrap_or(false),
+            markdown_fallback_raw:
+                raw.markdown_fallback_raw.unwrap_or(false),
+            zip_download:       raw.zip_download.unwrap_or(false),
+            zip_max_size:       raw.zip_max_size.unwrap_or(256 * 1024 * 1024),
+            max_depth:          raw.max_depth.unwrap_or(8),
+            not_found_status:   raw.not_found_status.unwrap_or(404),
+            canonical_urls:     raw.canonical_urls.unwrap_or(false),
+            rules:
+                raw.rules.unwrap_or_default()
+                   .into_iter()
+                   .map(Rule::from)
+                   .collect(),
+            normalize_backslashes:
+                raw.normalize_backslashes.unwrap_or(false),
+            stat_concurrency:   raw.stat_concurrency.unwrap_or(1),
+            non_utf8_names:     raw.non_utf8_names.unwrap_or(NonUtf8Names::Error),
+            health_path:        raw.health_path,
+            feeds:              raw.feeds.unwrap_or(false),
+            default_mime:
+                raw.default_mime
+                   .and_then(|m| m.parse().ok())
+                   .unwrap_or(octet_stream()),
+            pinned:
+                raw.pinned.unwrap_or_default()
+                   .iter()
+                   .map(OsString::from)
+                   .collect(),
+            canonical_link:     raw.canonical_link.unwrap_or(false),
+            verbatim_line_numbers:
+                raw.verbatim_line_numbers.unwrap_or(false),
+            passthrough_params:
+                raw.passthrough_params.unwrap_or_default(),
+            page_size:          raw.page_size.filter(|&n| n > 0),
+            pagination:         raw.pagination.unwrap_or(Pagination::Offset),
+            max_query_params:   raw.max_query_params.unwrap_or(64),
+            listing_tree_depth:
+                raw.listing_tree_depth.unwrap_or(1).max(1),
+            listing_tree_max_entries:
+                raw.listing_tree_max_entries.unwrap_or(1000),
+            default_host:       raw.default_host,
+            json_listing:       raw.json_listing.unwrap_or(false),
+            modified_precision:
+                raw.modified_precision.unwrap_or(ModifiedPrecision::Full),
+            language_variants:  raw.language_variants.unwrap_or(false),
+            language_pattern:
+                raw.language_pattern.unwrap_or(String::from("{name}.{lang}")),
+            default_language:   raw.default_language,
+            order_file:         raw.order_file,
+            listing_validators: raw.listing_validators.unwrap_or(false),
+            root_readme:        raw.root_readme.unwrap_or(false),
+            root_redirect:      raw.root_redirect,
+            charset:            raw.charset.unwrap_or(String::from("utf-8")),
+            download_dirs:
+                raw.download_dirs.unwrap_or_default()
+                   .iter()
+                   .map(|d| PathBuf::from(d.trim_matches('/')))
+                   .collect(),
+            strip_extensions_in_listing:
+                raw.strip_extensions_in_listing.unwrap_or(false),
+            stripped_extensions:
+                raw.stripped_extensions.unwrap_or_default()
+                   .iter()
+                   .map(OsString::from)
+                   .collect(),
+            extensionless_urls: raw.extensionless_urls.unwrap_or(false),
+            extensionless_extensions:
+                raw.extensionless_extensions.unwrap_or(vec![
+                    String::from("md"),
+                    String::from("html"),
+                ]),
+            json_errors:        raw.json_errors.unwrap_or(false),
+            request_id_header:  raw.request_id_header,
+            request_id_scheme:
+                raw.request_id_scheme.unwrap_or(RequestIdScheme::Random),
+            max_response_size:  raw.max_response_size,
+            force_raw:
+                raw.force_raw.unwrap_or_default()
+                   .iter()
+                   .map(OsString::from)
+                   .collect(),
+            force_verbatim:
+                raw.force_verbatim.unwrap_or_default()
+                   .iter()
+                   .map(OsString::from)
+                   .collect(),
+            canonicalize_clean_urls:
+                raw.canonicalize_clean_urls.unwrap_or(false),
+            canonical_url_form:
+                raw.canonical_url_form.unwrap_or(UrlForm::Extension),
+            content_security_policy: raw.content_security_policy,
+            json_pretty:        raw.json_pretty.unwrap_or(false),
+            blocked_user_agents:
+                raw.blocked_user_agents.unwrap_or_default(),
+            user_agent_matching:
+                raw.user_agent_matching.unwrap_or(PatternMatching::Substring),
+            blocked_user_agent_status:
+                raw.blocked_user_agent_status.unwrap_or(403),
+            block_missing_user_agent:
+                raw.block_missing_user_agent.unwrap_or(false),
+            footer:             raw.footer,
+            ignore_recent_writes_ms: raw.ignore_recent_writes_ms,
+            sidecar_extension:  raw.sidecar_extension,
+            sidecar_format:     raw.sidecar_format.unwrap_or(SidecarFormat::Toml),
+            sidecar_weight_field:
+                raw.sidecar_weight_field.unwrap_or(String::from("weight")),
+            markdown_preload:   raw.markdown_preload.unwrap_or(false),
+            rate_limit:         raw.rate_limit,
+            rate_limit_window_secs:
+                raw.rate_limit_window_secs.unwrap_or(60),
+            client_ip_header:   raw.client_ip_header,
+            latest_aliases:
+                raw.latest_aliases.unwrap_or_default()
+                   .into_iter()
+                   .map(LatestAlias::from)
+                   .collect(),
+            verbatim_ranges:    raw.verbatim_ranges.unwrap_or(false),
+            trailing_slash_redirects:
+                raw.trailing_slash_redirects.unwrap_or(false),
+            expose_permissions: raw.expose_permissions.unwrap_or(false),
+            capabilities_path:  raw.capabilities_path,
+            listing_modified_from_entries:
+                raw.listing_modified_from_entries.unwrap_or(false),
+            gzip:               raw.gzip.unwrap_or(false),
+            gzip_min_size:      raw.gzip_min_size.unwrap_or(1024),
+            compression:
+                raw.compression.unwrap_or_default()
+                   .into_iter()
+                   .map(|(mime, size)| (mime.to_ascii_lowercase(), size))
+                   .collect(),
+            compression_cache_size: raw.compression_cache_size.unwrap_or(0),
+            dirs_first:         raw.dirs_first.unwrap_or(true),
+            index_files:        raw.index_files.unwrap_or_default(),
+            max_page_size:      raw.max_page_size.filter(|&n| n > 0),
+            auth_user:          raw.auth_user,
+            auth_password:      raw.auth_password,
+            auth_realm:         raw.auth_realm.unwrap_or(String::from("Archive")),
+            error_pages:
+                raw.error_pages.unwrap_or_default()
+                   .into_iter()
+                   .filter_map(|(code, path)| code.parse().ok().map(|c| (c, path)))
+                   .collect(),
+            mime_overrides:
+                raw.mime_overrides.unwrap_or_default()
+                   .into_iter()
+                   .filter_map(|(ext, mime)| mime.parse().ok().map(|m| (OsString::from(ext), m)))
+                   .collect(),
+            allowed_globs:      raw.allowed_globs.unwrap_or_default(),
+            blocked_globs:      raw.blocked_globs.unwrap_or_default(),
         }
     }
 }
@@ -253,6 +1238,95 @@ struct RawConfig {
     pub allowed_file_names: Option<BTreeSet<String>>,
     pub blocked_file_names: Option<BTreeSet<String>>,
     pub markdown: Option<BTreeSet<String>>,
+    pub expose_fs_paths: Option<bool>,
+    pub cache: Option<RawCachePolicy>,
+    pub max_path_length: Option<usize>,
+    pub max_path_segments: Option<usize>,
+    pub count_children: Option<bool>,
+    pub markdown_heading_offset: Option<u32>,
+    pub hide_in_listing: Option<BTreeSet<String>>,
+    pub markdown_negotiation: Option<bool>,
+    pub markdown_fallback_raw: Option<bool>,
+    pub zip_download: Option<bool>,
+    pub zip_max_size: Option<u64>,
+    pub max_depth: Option<usize>,
+    pub not_found_status: Option<u16>,
+    pub canonical_urls: Option<bool>,
+    pub rules: Option<Vec<RawRule>>,
+    pub normalize_backslashes: Option<bool>,
+    pub stat_concurrency: Option<usize>,
+    pub non_utf8_names: Option<NonUtf8Names>,
+    pub health_path: Option<String>,
+    pub feeds: Option<bool>,
+    pub default_mime: Option<String>,
+    pub pinned: Option<Vec<String>>,
+    pub canonical_link: Option<bool>,
+    pub verbatim_line_numbers: Option<bool>,
+    pub passthrough_params: Option<BTreeSet<String>>,
+    pub page_size: Option<usize>,
+    pub pagination: Option<Pagination>,
+    pub max_query_params: Option<usize>,
+    pub listing_tree_depth: Option<usize>,
+    pub listing_tree_max_entries: Option<usize>,
+    pub default_host: Option<String>,
+    pub json_listing: Option<bool>,
+    pub modified_precision: Option<ModifiedPrecision>,
+    pub language_variants: Option<bool>,
+    pub language_pattern: Option<String>,
+    pub default_language: Option<String>,
+    pub order_file: Option<String>,
+    pub listing_validators: Option<bool>,
+    pub root_readme: Option<bool>,
+    pub root_redirect: Option<String>,
+    pub charset: Option<String>,
+    pub download_dirs: Option<Vec<String>>,
+    pub strip_extensions_in_listing: Option<bool>,
+    pub stripped_extensions: Option<BTreeSet<String>>,
+    pub extensionless_urls: Option<bool>,
+    pub extensionless_extensions: Option<Vec<String>>,
+    pub json_errors: Option<bool>,
+    pub request_id_header: Option<String>,
+    pub request_id_scheme: Option<RequestIdScheme>,
+    pub max_response_size: Option<usize>,
+    pub force_raw: Option<BTreeSet<String>>,
+    pub force_verbatim: Option<BTreeSet<String>>,
+    pub canonicalize_clean_urls: Option<bool>,
+    pub canonical_url_form: Option<UrlForm>,
+    pub content_security_policy: Option<String>,
+    pub json_pretty: Option<bool>,
+    pub blocked_user_agents: Option<Vec<String>>,
+    pub user_agent_matching: Option<PatternMatching>,
+    pub blocked_user_agent_status: Option<u16>,
+    pub block_missing_user_agent: Option<bool>,
+    pub footer: Option<String>,
+    pub ignore_recent_writes_ms: Option<u64>,
+    pub sidecar_extension: Option<String>,
+    pub sidecar_format: Option<SidecarFormat>,
+    pub sidecar_weight_field: Option<String>,
+    pub markdown_preload: Option<bool>,
+    pub rate_limit: Option<u32>,
+    pub rate_limit_window_secs: Option<u64>,
+    pub client_ip_header: Option<String>,
+    pub latest_aliases: Option<Vec<RawLatestAlias>>,
+    pub verbatim_ranges: Option<bool>,
+    pub trailing_slash_redirects: Option<bool>,
+    pub expose_permissions: Option<bool>,
+    pub capabilities_path: Option<String>,
+    pub listing_modified_from_entries: Option<bool>,
+    pub gzip: Option<bool>,
+    pub gzip_min_size: Option<usize>,
+    pub compression: Option<BTreeMap<String, usize>>,
+    pub compression_cache_size: Option<usize>,
+    pub dirs_first: Option<bool>,
+    pub index_files: Option<Vec<String>>,
+    pub max_page_size: Option<usize>,
+    pub auth_user: Option<String>,
+    pub auth_password: Option<String>,
+    pub auth_realm: Option<String>,
+    pub error_pages: Option<BTreeMap<String, String>>,
+    pub mime_overrides: Option<BTreeMap<String, String>>,
+    pub allowed_globs: Option<Vec<String>>,
+    pub blocked_globs: Option<Vec<String>>,
 }
 
 impl Default for RawConfig {
@@ -269,6 +1343,374 @@ impl Default for RawConfig {
                 set.insert(String::from("md"));
                 set
             }),
+            expose_fs_paths: None,
+            cache: None,
+            max_path_length: None,
+            max_path_segments: None,
+            count_children: None,
+            markdown_heading_offset: None,
+            hide_in_listing: None,
+            markdown_negotiation: None,
+            markdown_fallback_raw: None,
+            zip_download: None,
+            zip_max_size: None,
+            max_depth: None,
+            not_found_status: None,
+            canonical_urls: None,
+            rules: None,
+            normalize_backslashes: None,
+            stat_concurrency: None,
+            non_utf8_names: None,
+            health_path: None,
+            feeds: None,
+            default_mime: None,
+            pinned: None,
+            canonical_link: None,
+            verbatim_line_numbers: None,
+            passthrough_params: None,
+            page_size: None,
+            pagination: None,
+            max_query_params: None,
+            listing_tree_depth: None,
+            listing_tree_max_entries: None,
+            default_host: None,
+            json_listing: None,
+            modified_precision: None,
+            language_variants: None,
+            language_pattern: None,
+            default_language: None,
+            order_file: None,
+            listing_validators: None,
+            root_readme: None,
+            root_redirect: None,
+            charset: None,
+            download_dirs: None,
+            strip_extensions_in_listing: None,
+            stripped_extensions: None,
+            extensionless_urls: None,
+            extensionless_extensions: None,
+            json_errors: None,
+            request_id_header: None,
+            request_id_scheme: None,
+            max_response_size: None,
+            force_raw: None,
+            force_verbatim: None,
+            canonicalize_clean_urls: None,
+            canonical_url_form: None,
+            content_security_policy: None,
+            json_pretty: None,
+            blocked_user_agents: None,
+            user_agent_matching: None,
+            blocked_user_agent_status: None,
+            block_missing_user_agent: None,
+            footer: None,
+            ignore_recent_writes_ms: None,
+            sidecar_extension: None,
+            sidecar_format: None,
+            sidecar_weight_field: None,
+            markdown_preload: None,
+            rate_limit: None,
+            rate_limit_window_secs: None,
+            client_ip_header: None,
+            latest_aliases: None,
+            verbatim_ranges: None,
+            trailing_slash_redirects: None,
+            expose_permissions: None,
+            capabilities_path: None,
+            listing_modified_from_entries: None,
+            gzip: None,
+            gzip_min_size: None,
+            compression: None,
+            compression_cache_size: None,
+            dirs_first: None,
+            index_files: None,
+            max_page_size: None,
+            auth_user: None,
+            auth_password: None,
+            auth_realm: None,
+            error_pages: None,
+            mime_overrides: None,
+            allowed_globs: None,
+            blocked_globs: None,
+        }
+    }
+}
+
+impl RawConfig {
+    // Checks the values that cannot be checked by their types alone
+    fn validate(&self) -> Result<(), String> {
+        if let Some(ref m) = self.default_mime {
+            m.parse::<Mime>()
+                .map_err(|_| format!("Invalid Mime for `default_mime': {}", m))?;
+        }
+        if let Some(ref h) = self.default_host {
+            Url::parse(&format!("http://{}/", h))
+                .ok()
+                .filter(|u| u.host_str().is_some() && u.path() == "/")
+                .ok_or_else(|| format!("Invalid host for `default_host': {}", h))?;
+        }
+        if let Some(ref p) = self.language_pattern {
+            if !p.contains("{lang}") {
+                return Err(format!("`language_pattern' does not contain {{lang}}: {}", p));
+            }
+        }
+        if self.rate_limit == Some(0) || self.rate_limit_window_secs == Some(0) {
+            return Err(String::from("`rate_limit' and `rate_limit_window_secs' must not be 0"));
         }
+        if self.auth_user.is_some() != self.auth_password.is_some() {
+            return Err(String::from("`auth_user' and `auth_password' must be set together"));
+        }
+        for code in self.error_pages.iter().flat_map(|p| p.keys()) {
+            if code.parse::<u16>().ok().filter(|c| *c >= 400 && *c < 600).is_none() {
+                return Err(format!("Not an error status in `error_pages': {}", code));
+            }
+        }
+        for (ext, m) in self.mime_overrides.iter().flatten() {
+            m.parse::<Mime>()
+                .map_err(|_| format!("Invalid Mime for `{}' in `mime_overrides': {}", ext, m))?;
+        }
+        for mime in self.compression.iter().flat_map(|c| c.keys()) {
+            match mime.parse::<Mime>() {
+                Ok(ref m) if m.2.is_empty() => (),
+                _ => return Err(format!("Not a content type in `compression': {}", mime)),
+            }
+        }
+        for pattern in self.allowed_globs.iter().flatten() {
+            if pattern.is_empty() || pattern.contains('/') {
+                return Err(format!("Not a pattern of file names in `allowed_globs': {}", pattern));
+            }
+        }
+        for pattern in self.blocked_globs.iter().flatten() {
+            if pattern.is_empty() || pattern.contains('/') {
+                return Err(format!("Not a pattern of file names in `blocked_globs': {}", pattern));
+            }
+        }
+        for name in self.index_files.iter().flatten() {
+            if Path::new(name).file_name() != Some(OsStr::new(name)) {
+                return Err(format!("Not a file name in `index_files': {}", name));
+            }
+        }
+        if let (Some(ref r), Some(ref v)) = (&self.force_raw, &self.force_verbatim) {
+            if let Some(ext) = r.intersection(v).next() {
+                return Err(format!("Extension in both `force_raw' and `force_verbatim': {}", ext));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct RawRule {
+    pub path: String,
+    pub allow: Option<BTreeSet<String>>,
+    pub block: Option<BTreeSet<String>>,
+    pub force_raw: Option<BTreeSet<String>>,
+    pub force_verbatim: Option<BTreeSet<String>>,
+}
+
+impl From<RawRule> for Rule {
+    fn from(raw: RawRule) -> Self {
+        Rule {
+            // The path is relative to the served root even if written as absolute
+            path:   PathBuf::from(raw.path.trim_matches('/')),
+            allow:
+                raw.allow.unwrap_or_default()
+                   .iter()
+                   .map(OsString::from)
+                   .collect(),
+            block:
+                raw.block.unwrap_or_default()
+                   .iter()
+                   .map(OsString::from)
+                   .collect(),
+            force_raw:
+                raw.force_raw.unwrap_or_default()
+                   .iter()
+                   .map(OsString::from)
+                   .collect(),
+            force_verbatim:
+                raw.force_verbatim.unwrap_or_default()
+                   .iter()
+                   .map(OsString::from)
+                   .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct RawLatestAlias {
+    pub path: String,
+    pub pattern: Option<String>,
+}
+
+impl From<RawLatestAlias> for LatestAlias {
+    fn from(raw: RawLatestAlias) -> Self {
+        LatestAlias {
+            // The path is relative to the served root even if written as absolute
+            path:       PathBuf::from(raw.path.trim_matches('/')),
+            pattern:    raw.pattern,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct RawCachePolicy {
+    pub default: Option<u64>,
+    pub listing: Option<u64>,
+    pub extensions: Option<BTreeMap<String, u64>>,
+    pub immutable_hash_length: Option<usize>,
+}
+
+impl From<RawCachePolicy> for CachePolicy {
+    fn from(raw: RawCachePolicy) -> Self {
+        CachePolicy {
+            default:    raw.default,
+            listing:    raw.listing,
+            extensions:
+                raw.extensions.unwrap_or_default()
+                   .into_iter()
+                   .map(|(ext, max_age)| (OsString::from(ext), max_age))
+                   .collect(),
+            immutable_hash_length: raw.immutable_hash_length,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use toml;
+
+    use std::ffi::OsString;
+    use std::path::Path;
+
+    use super::*;
+    use testing::TempDir;
+
+    fn rule(path: &str, allow: &[&str], block: &[&str], force_verbatim: &[&str]) -> Rule {
+        let set = |names: &[&str]| names.iter().map(OsString::from).collect();
+        Rule {
+            path: PathBuf::from(path),
+            allow: set(allow),
+            block: set(block),
+            force_raw: BTreeSet::new(),
+            force_verbatim: set(force_verbatim),
+        }
+    }
+
+    // Parses the configuration the way `Config::load' does
+    fn parse(content: &str) -> Config {
+        let raw = toml::from_str::<RawConfig>(content).unwrap();
+        raw.validate().unwrap();
+        Config::from(raw)
+    }
+
+    #[test]
+    fn example_in_the_documentation_is_read_as_documented() {
+        let source = include_str!("config.rs");
+        let start = source.find("/// ```toml\n").unwrap();
+        let end = start + source[start..].find("/// ```\n").unwrap();
+        let example : String = source[start..end].lines()
+            .skip(1)
+            .map(|l| l.trim_start_matches("///").trim_start())
+            .collect::<Vec<&str>>()
+            .join("\n");
+        let config = parse(&example);
+
+        assert_eq!(config.root_dir, "src");
+        assert_eq!(config.listen, "localhost:5000");
+        assert!(!config.allow_all);
+        assert!(config.allowed_extensions.contains(&OsString::from("rs")));
+        assert_eq!(config.allowed_globs, vec!["*.min.js", "report-*.csv"]);
+        assert_eq!(config.markdown.len(), 1);
+        assert!(config.hide_in_listing.contains(&OsString::from(".DS_Store")));
+        assert!(!config.expose_fs_paths);
+        assert_eq!(config.cache.default, Some(60));
+        assert_eq!(config.cache.immutable_hash_length, Some(8));
+        assert!(config.gzip);
+        assert_eq!(config.compression_threshold(&"application/json".parse().unwrap()), Some(256));
+        assert_eq!(config.compression_threshold(&"text/html; charset=utf-8".parse().unwrap()),
+            Some(4096));
+        assert_eq!(config.compression_threshold(&"application/rss+xml".parse().unwrap()), None);
+        assert_eq!(config.rules.len(), 2);
+        assert_eq!(config.rules[0].path, Path::new("config"));
+        assert!(config.rules[0].allow.contains(&OsString::from(".env")));
+        assert!(config.rules[1].force_raw.contains(&OsString::from("txt")));
+        assert_eq!(config.latest_aliases.len(), 1);
+        assert_eq!(config.latest_aliases[0].pattern.as_deref(), Some("*.tar.gz"));
+    }
+
+    #[test]
+    fn scoped_rules_take_precedence_over_global_names() {
+        let dir = TempDir::new();
+        let mut config = Config {
+            root_dir: dir.path().to_string_lossy().into_owned(),
+            allow_all: true,
+            ..Config::default()
+        };
+        config.blocked_file_names.insert(OsString::from("secret.txt"));
+        config.force_raw.insert(OsString::from("txt"));
+        config.rules = vec![
+            rule("public", &["secret.txt", ".env"], &[], &["txt"]),
+            rule("public/inner", &[], &["secret.txt"], &[]),
+        ];
+
+        let top = dir.file("secret.txt", "");
+        let public = dir.file("public/secret.txt", "");
+        let dot = dir.file("public/.env", "");
+        let inner = dir.file("public/inner/secret.txt", "");
+        let other = dir.file("public/inner/other.txt", "");
+
+        // Without a rule the global names apply
+        assert_eq!(config.method_for(&top).unwrap(), None);
+        // A rule allows what the global names block, and forces its own methods
+        assert_eq!(config.method_for(&public).unwrap(), Some(AccessMethod::Verbatim));
+        assert_eq!(config.method_for(&dot).unwrap(), Some(AccessMethod::Verbatim));
+        // The most specific rule wins over the rules of the directories above
+        assert_eq!(config.method_for(&inner).unwrap(), None);
+        assert_eq!(config.method_for(&other).unwrap(), Some(AccessMethod::Raw));
+    }
+
+    #[test]
+    fn blocked_globs_take_precedence_over_scoped_rules() {
+        let dir = TempDir::new();
+        let mut config = Config {
+            root_dir: dir.path().to_string_lossy().into_owned(),
+            allow_all: true,
+            ..Config::default()
+        };
+        config.blocked_globs.push("*.key".to_owned());
+        config.rules = vec![rule("keys", &["server.key"], &[], &[])];
+
+        let key = dir.file("keys/server.key", "");
+        assert_eq!(config.method_for(&key).unwrap(), None);
+    }
+
+    #[test]
+    fn device_names_and_trailing_dots_and_spaces_are_reserved() {
+        for name in &["CON", "con.txt", "Nul", "COM1", "lpt9.log", "aux .md", "foo.", "foo "] {
+            assert!(is_reserved_name(name), "{}", name);
+        }
+        for name in &["CONFIG", "com0", "COM10", "foo.txt", "console.log", ".env"] {
+            assert!(!is_reserved_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn extensions_can_be_forced_either_way() {
+        let dir = TempDir::new();
+        let svg = dir.file("logo.svg", "<svg/>");
+        let csv = dir.file("data.csv", "a,b");
+        let mut config = Config {
+            root_dir: dir.path().to_string_lossy().into_owned(),
+            allow_all: true,
+            ..Config::default()
+        };
+        assert_eq!(config.method_for(&svg).unwrap(), Some(AccessMethod::Raw));
+        assert_eq!(config.method_for(&csv).unwrap(), Some(AccessMethod::Verbatim));
+
+        config.force_verbatim.insert(OsString::from("svg"));
+        config.force_raw.insert(OsString::from("csv"));
+        assert_eq!(config.method_for(&svg).unwrap(), Some(AccessMethod::Verbatim));
+        assert_eq!(config.method_for(&csv).unwrap(), Some(AccessMethod::Raw));
     }
 }
@@ -0,0 +1,240 @@
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use flate2::{Compression, Crc};
+use flate2::write::DeflateEncoder;
+use iron::response::WriteBody;
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use config::*;
+use walk::*;
+
+// The largest size and number of files an archive can have without the ZIP64 extensions
+const MAX_ZIP_SIZE: u64 = 0xffff_ffff;
+const MAX_ZIP_FILES: usize = 0xffff;
+
+/// Why a directory cannot be downloaded as a zip archive
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ZipRefusal {
+    /// The files add up to more than `zip_max_size` bytes, or are too many for an archive
+    TooLarge,
+    /// Some subdirectories are deeper than `max_depth`, so the archive would be incomplete
+    TooDeep,
+}
+
+/// A zip archive of the servable files in a directory, written as it is sent
+///
+/// The files are only read while the archive is written. Files that cannot be opened by then,
+/// such as those removed in the meantime, are left out.
+///
+pub struct ZipStream {
+    files: Vec<ZipFile>,
+}
+
+struct ZipFile {
+    path: PathBuf,
+    name: String,
+    modified: Option<SystemTime>,
+}
+
+// A file written into the archive, as recorded in the central directory
+struct Written {
+    name: String,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Gathers the servable files in a directory to be packed into a zip archive
+///
+/// Directories with subdirectories more than `max_depth` levels deep are refused, rather than
+/// packed without them. Symbolic links are never followed.
+///
+/// # Arguments
+/// * `dir`    - The path to the directory in the file system
+/// * `config` - The configuration deciding which files are servable
+///
+/// # Error
+/// Returns an error if the directory cannot be read.
+///
+pub fn zip_directory(dir: &Path, config: &Config) -> io::Result<Result<ZipStream, ZipRefusal>> {
+    let walk = walk_with_limit(dir, config, WalkLimit::of(config))?;
+    if walk.truncated {
+        return Ok(Err(ZipRefusal::TooDeep));
+    }
+    let max_size = config.zip_max_size.min(MAX_ZIP_SIZE);
+    let mut files = Vec::new();
+    let mut size = 0;
+    for e in walk.entries.iter().filter(|e| e.method.is_file() && !e.is_symlink) {
+        // Names that cannot be represented in the archive are left out
+        let name = match zip_name(&e.rel_path) {
+            Some(n) => n,
+            None => continue,
+        };
+        size += e.metadata.len();
+        if size > max_size || files.len() >= MAX_ZIP_FILES {
+            return Ok(Err(ZipRefusal::TooLarge));
+        }
+        files.push(ZipFile {
+            path: e.path.clone(),
+            name,
+            modified: e.metadata.modified().ok(),
+        });
+    }
+    Ok(Ok(ZipStream { files }))
+}
+
+impl WriteBody for ZipStream {
+    // Each file is preceded by a header stating its name,
+    // and followed by a descriptor stating its checksum and sizes once they are known
+    // The central directory listing all of the files comes last
+    fn write_body(&mut self, res: &mut dyn Write) -> io::Result<()> {
+        let mut out = CountingWriter { inner: res, count: 0 };
+        let mut written = Vec::with_capacity(self.files.len());
+        for f in &self.files {
+            let mut file = match File::open(&f.path) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            let offset = out.count;
+            let (time, date) = dos_time(f.modified);
+            out.write_all(&0x04034b50u32.to_le_bytes())?;
+            write_file_header(&mut out, time, date, 0, 0, 0)?;
+            out.write_all(&(f.name.len() as u16).to_le_bytes())?;
+            out.write_all(&0u16.to_le_bytes())?;
+            out.write_all(f.name.as_bytes())?;
+
+            let start = out.count;
+            let mut crc = Crc::new();
+            let mut encoder = DeflateEncoder::new(&mut out, Compression::default());
+            let mut buf = [0; 64 * 1024];
+            loop {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                crc.update(&buf[..n]);
+                encoder.write_all(&buf[..n])?;
+            }
+            encoder.finish()?;
+            // A file that grew past the limit since it was looked at cannot be described
+            let compressed_size = to_u32(out.count - start)?;
+            let size = to_u32(u64::from(crc.amount()))?;
+
+            out.write_all(&0x08074b50u32.to_le_bytes())?;
+            out.write_all(&crc.sum().to_le_bytes())?;
+            out.write_all(&compressed_size.to_le_bytes())?;
+            out.write_all(&size.to_le_bytes())?;
+            written.push(Written {
+                name: f.name.clone(),
+                time,
+                date,
+                crc: crc.sum(),
+                compressed_size,
+                size,
+                offset: to_u32(offset)?,
+            });
+        }
+
+        let start = out.count;
+        for w in &written {
+            out.write_all(&0x02014b50u32.to_le_bytes())?;
+            out.write_all(&20u16.to_le_bytes())?;
+            write_file_header(&mut out, w.time, w.date, w.crc, w.compressed_size, w.size)?;
+            out.write_all(&(w.name.len() as u16).to_le_bytes())?;
+            // No extra field, comment, disk number, or attributes
+            out.write_all(&[0; 12])?;
+            out.write_all(&w.offset.to_le_bytes())?;
+            out.write_all(w.name.as_bytes())?;
+        }
+        let size = to_u32(out.count - start)?;
+        let start = to_u32(start)?;
+        out.write_all(&0x06054b50u32.to_le_bytes())?;
+        out.write_all(&[0; 4])?;
+        out.write_all(&(written.len() as u16).to_le_bytes())?;
+        out.write_all(&(written.len() as u16).to_le_bytes())?;
+        out.write_all(&size.to_le_bytes())?;
+        out.write_all(&start.to_le_bytes())?;
+        out.write_all(&0u16.to_le_bytes())?;
+        out.flush()
+    }
+}
+
+// Writes the fields shared by the local and the central headers of a file,
+// from the version needed to extract it up to its sizes
+//
+// The names are always UTF-8, and the files are always deflated
+fn write_file_header<W: Write>(
+    out: &mut W,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+) -> io::Result<()> {
+    out.write_all(&20u16.to_le_bytes())?;
+    // The sizes follow the data, and the name is UTF-8
+    out.write_all(&0x0808u16.to_le_bytes())?;
+    out.write_all(&8u16.to_le_bytes())?;
+    out.write_all(&time.to_le_bytes())?;
+    out.write_all(&date.to_le_bytes())?;
+    out.write_all(&crc.to_le_bytes())?;
+    out.write_all(&compressed_size.to_le_bytes())?;
+    out.write_all(&size.to_le_bytes())
+}
+
+// The modification time in the format of MS-DOS, as used by zip archives
+// Times before 1980 cannot be represented, and are taken to be the start of 1980
+fn dos_time(modified: Option<SystemTime>) -> (u16, u16) {
+    let time = match modified.map(DateTime::<Utc>::from) {
+        Some(t) if t.year() >= 1980 && t.year() < 2108 => t,
+        _ => return (0, 1 << 5 | 1),
+    };
+    (
+        (time.hour() << 11 | time.minute() << 5 | (time.second() / 2)) as u16,
+        ((time.year() - 1980) << 9 | (time.month() << 5) as i32 | time.day() as i32) as u16,
+    )
+}
+
+fn to_u32(n: u64) -> io::Result<u32> {
+    if n > MAX_ZIP_SIZE {
+        return Err(io::Error::other("The archive is too large"));
+    }
+    Ok(n as u32)
+}
+
+// Counts the bytes written, to know where each part of the archive begins
+struct CountingWriter<'a> {
+    inner: &'a mut dyn Write,
+    count: u64,
+}
+
+impl<'a> Write for CountingWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Names in zip archives are always separated by forward slashes
+fn zip_name(rel_path: &Path) -> Option<String> {
+    let mut parts = Vec::new();
+    for c in rel_path.components() {
+        match c {
+            Component::Normal(s) => parts.push(s.to_str()?),
+            _ => return None,
+        }
+    }
+    Some(parts.join("/"))
+}
@@ -1,34 +1,197 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Timelike, Utc};
+use url::percent_encoding::{percent_encode, PATH_SEGMENT_ENCODE_SET};
 
-use std::fs::DirEntry;
+use std::ffi::{OsStr, OsString};
+use std::fs::{DirEntry, Metadata};
 use std::io;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+use config::ModifiedPrecision;
+use renderer::escape_html;
 
 /// Directory entry used for rendering
 ///
 /// The `struct Entry` can by converted from Rust's standard `DirEntry`. It contains only the data
 /// needed for the purpose of rendering an directory index.
 ///
+/// The names are taken from the file system as they are, and may contain markup such as
+/// `<script>`. Use `escaped_file_name` and `escaped_display_name` to put them in a page.
+///
 #[derive(Debug, Clone)]
 pub struct Entry {
     pub is_dir: bool,
+    /// The name of the entry for display
+    ///
+    /// If the entry is built using `from_lossy`, invalid UTF-8 sequences in the name are replaced
+    /// by `U+FFFD REPLACEMENT CHARACTER`.
     pub file_name: String,
+    /// The name of the entry as it is shown in listings
+    ///
+    /// This is the same as `file_name`, unless `strip_extensions_in_listing` is turned on in the
+    /// configuration, in which case the extension is left out.
+    pub display_name: String,
+    /// The name of the entry as it is in the file system
+    pub raw_file_name: OsString,
+    /// The percent-encoded url to the entry, relative to the directory containing it
+    ///
+    /// The url of a directory ends with a slash. Quotes and angle brackets are encoded, so the url
+    /// can be put in a double-quoted attribute as it is.
+    pub href: String,
     pub modified: String,
+    /// The size of the file in bytes
+    ///
+    /// This is always 0 for directories.
+    pub size: u64,
+    /// The time the entry was last modified
+    ///
+    /// This is rounded down to the day, or left out, according to `modified_precision` in the
+    /// configuration. `modified` is likewise shortened to the date, or left empty.
+    pub modified_time: Option<DateTime<Utc>>,
+    /// The number of servable entries in the directory
+    ///
+    /// This is only counted for directories when `count_children` is turned on in the
+    /// configuration, and is `None` otherwise.
+    pub child_count: Option<usize>,
+    /// The permission bits of the entry, such as `0o755`
+    ///
+    /// This is only provided on Unix when `expose_permissions` is turned on in the configuration,
+    /// and is `None` otherwise.
+    pub mode: Option<u32>,
 }
 
 impl Entry {
+    /// Builds the entry from a `DirEntry`.
+    ///
+    /// # Error
+    /// Returns an error if the metadata cannot be accessed, or if the name is not valid UTF-8.
+    ///
     pub fn from(e: &DirEntry) -> io::Result<Self> {
-        let md = e.metadata()?;
-        Ok(Entry {
-            is_dir: md.is_dir(),
-            file_name: String::from(
-                e.file_name()
-                 .into_string()
-                 .map_err(|_| io::Error::new(
-                     io::ErrorKind::Other,
-                     "File name is not valid UTF-8."
-                 ))?),
-            modified: DateTime::<Utc>::from(md.modified()?)
-                .format("%Y-%m-%d %R").to_string(),
-        })
+        if e.file_name().to_str().is_none() {
+            return Err(io::Error::other(
+                "File name is not valid UTF-8."
+            ));
+        }
+        Entry::from_lossy(e)
+    }
+
+    /// Builds the entry from a `DirEntry`, even if its name is not valid UTF-8.
+    ///
+    /// # Error
+    /// Returns an error if the metadata cannot be accessed.
+    ///
+    pub fn from_lossy(e: &DirEntry) -> io::Result<Self> {
+        entry_with_metadata(e.file_name(), &e.metadata()?)
+    }
+
+    /// Coarsens or hides the modification time of the entry.
+    ///
+    /// # Arguments
+    /// * `precision` - How precisely the modification time is kept
+    ///
+    pub fn with_precision(mut self, precision: ModifiedPrecision) -> Self {
+        match precision {
+            ModifiedPrecision::Full => (),
+            ModifiedPrecision::Date => {
+                self.modified_time = self.modified_time
+                    .and_then(|t| t.with_hour(0))
+                    .and_then(|t| t.with_minute(0))
+                    .and_then(|t| t.with_second(0))
+                    .and_then(|t| t.with_nanosecond(0));
+                self.modified = self.modified_time
+                    .map(|t| t.format("%Y-%m-%d").to_string())
+                    .unwrap_or_default();
+            },
+            ModifiedPrecision::Hidden => {
+                self.modified_time = None;
+                self.modified = String::new();
+            },
+        }
+        self
+    }
+
+    /// The name of the entry, escaped to be put in HTML
+    pub fn escaped_file_name(&self) -> String {
+        escape_html(&self.file_name)
+    }
+
+    /// The name of the entry as it is shown in listings, escaped to be put in HTML
+    pub fn escaped_display_name(&self) -> String {
+        escape_html(&self.display_name)
+    }
+
+    /// Formats the size of the entry in binary units, such as `1.2 MiB`
+    ///
+    /// Sizes below 1 KiB are given in whole bytes, such as `512 B`.
+    ///
+    pub fn human_size(&self) -> String {
+        const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+        if self.size < 1024 {
+            return format!("{} B", self.size);
+        }
+        let mut size = self.size as f64 / 1024.0;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+// Builds the entry from its name and its metadata, read beforehand
+// The name need not be valid UTF-8
+pub fn entry_with_metadata(raw_file_name: OsString, md: &Metadata) -> io::Result<Entry> {
+    let modified_time = DateTime::<Utc>::from(md.modified()?);
+    Ok(Entry {
+        is_dir: md.is_dir(),
+        href: href_for(&raw_file_name, md.is_dir()),
+        file_name: raw_file_name.to_string_lossy().into_owned(),
+        display_name: raw_file_name.to_string_lossy().into_owned(),
+        raw_file_name,
+        modified: modified_time.format("%Y-%m-%d %R").to_string(),
+        size: if md.is_dir() { 0 } else { md.len() },
+        modified_time: Some(modified_time),
+        child_count: None,
+        mode: mode_of(md),
+    })
+}
+
+// The permission bits in the metadata
+#[cfg(unix)]
+fn mode_of(md: &Metadata) -> Option<u32> {
+    Some(md.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn mode_of(_: &Metadata) -> Option<u32> {
+    None
+}
+
+// Builds the relative url to an entry with the specified name
+fn href_for(file_name: &OsStr, is_dir: bool) -> String {
+    let mut href = percent_encode(name_bytes(file_name).as_ref(), PATH_SEGMENT_ENCODE_SET)
+        .to_string();
+    // A colon in the first segment would make the url look like it has a scheme
+    if href.contains(':') {
+        href.insert_str(0, "./");
+    }
+    if is_dir {
+        href.push('/');
     }
+    href
+}
+
+// The bytes making up the file name
+// On Unix these are the bytes in the file system, which need not be valid UTF-8
+#[cfg(unix)]
+pub fn name_bytes(file_name: &OsStr) -> Vec<u8> {
+    file_name.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+pub fn name_bytes(file_name: &OsStr) -> Vec<u8> {
+    file_name.to_string_lossy().into_owned().into_bytes()
 }
@@ -0,0 +1,56 @@
+use url::Url;
+
+use entry::Entry;
+
+/// Builds an RSS feed of the entries in a directory
+///
+/// # Arguments
+/// * `title`   - The title of the feed
+/// * `link`    - The url of the directory, against which the entries are linked
+/// * `entries` - The entries in the directory, in the order they appear in the feed
+///
+pub fn rss(title: &str, link: &Url, entries: &[Entry]) -> String {
+    let mut result = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<rss version=\"2.0\"><channel>"
+    );
+    result.push_str(&format!(
+        "<title>{}</title><link>{}</link><description>{}</description>",
+        escape_xml(title),
+        escape_xml(link.as_str()),
+        escape_xml(title)
+    ));
+    for e in entries {
+        let entry_link = link.join(&e.href)
+            .map(|u| u.into_string())
+            .unwrap_or_else(|_| e.href.clone());
+        // The date is left out if the modification time is hidden
+        let pub_date = e.modified_time
+            .map(|t| format!("<pubDate>{}</pubDate>", t.to_rfc2822()))
+            .unwrap_or_default();
+        result.push_str(&format!(
+            "<item><title>{}</title><link>{}</link><guid>{}</guid>{}</item>",
+            escape_xml(&e.file_name),
+            escape_xml(&entry_link),
+            escape_xml(&entry_link),
+            pub_date
+        ));
+    }
+    result.push_str("</channel></rss>");
+    result
+}
+
+// Escapes the characters with special meanings in XML
+fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
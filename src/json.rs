@@ -0,0 +1,162 @@
+use serde::Serialize;
+use serde_json;
+
+use config::{Config, Pagination};
+use listing::Listing;
+
+/// The versions of the schema of JSON listings that can be served, oldest first
+pub const SCHEMA_VERSIONS: &[u32] = &[1];
+
+// Version 1 of the schema
+//
+// The names and types of the fields below are part of the schema,
+// so they must not change within a version
+//
+// {
+//     "schema_version": 1,
+//     "path": "docs",              The path to the directory, relative to the served root
+//     "next_page": "2",            The `page' or `cursor' asking for the next page, or null
+//     "entries": [
+//         {
+//             "name": "guide.md",
+//             "href": "guide.md",  Relative to the directory, percent encoded
+//             "is_dir": false,
+//             "size": 1234,        In bytes, or null for directories
+//             "modified": "2018-02-01T12:34:56+00:00",
+//                                  RFC 3339, in UTC, or null if hidden
+//             "child_count": null  Only counted if `count_children' is turned on
+//         }
+//     ]
+// }
+#[derive(Serialize)]
+struct ListingV1<'a> {
+    schema_version: u32,
+    path: &'a str,
+    next_page: Option<&'a str>,
+    entries: Vec<EntryV1<'a>>,
+}
+
+#[derive(Serialize)]
+struct EntryV1<'a> {
+    name: &'a str,
+    href: &'a str,
+    is_dir: bool,
+    size: Option<u64>,
+    modified: Option<String>,
+    child_count: Option<usize>,
+}
+
+/// Serialises a directory listing as JSON
+///
+/// # Arguments
+/// * `path`    - The path to the directory, relative to the served root
+/// * `listing` - The listing of the directory
+/// * `version` - The version of the schema to follow
+/// * `pretty`  - Whether or not the JSON is indented
+///
+/// Returns `None` if the version of the schema is not supported.
+///
+pub fn listing(path: &str, listing: &Listing, version: u32, pretty: bool) -> Option<String> {
+    match version {
+        1 => to_string(&ListingV1 {
+            schema_version: 1,
+            path,
+            next_page: listing.next_page.as_deref(),
+            entries: listing.entries.iter()
+                .map(|e| EntryV1 {
+                    name: &e.file_name,
+                    href: &e.href,
+                    is_dir: e.is_dir,
+                    size: if e.is_dir { None } else { Some(e.size) },
+                    modified: e.modified_time.map(|t| t.to_rfc3339()),
+                    child_count: e.child_count,
+                })
+                .collect(),
+        }, pretty),
+        _ => None,
+    }
+}
+
+fn to_string<T: Serialize>(value: &T, pretty: bool) -> Option<String> {
+    if pretty {
+        serde_json::to_string_pretty(value).ok()
+    } else {
+        serde_json::to_string(value).ok()
+    }
+}
+
+#[derive(Serialize)]
+struct Error<'a> {
+    error: u16,
+    message: &'a str,
+    path: &'a str,
+}
+
+/// Serialises an error as JSON, such as `{"error":404,"message":"...","path":"docs/a.md"}`
+///
+/// # Arguments
+/// * `code`    - The HTTP status code for the error
+/// * `message` - A message describing the error
+/// * `path`    - The path requested, relative to the served root
+///
+pub fn error(code: u16, message: &str, path: &str) -> String {
+    serde_json::to_string(&Error {
+        error: code,
+        message,
+        path,
+    }).unwrap_or_default()
+}
+
+// The features of the server that clients can make use of
+//
+// Like listings, the names and types of the fields are kept stable,
+// and new fields are only ever added
+#[derive(Serialize)]
+struct Capabilities<'a> {
+    version: &'a str,
+    json_listing: bool,
+    json_schema_versions: &'a [u32],
+    json_errors: bool,
+    feeds: bool,
+    zip_download: bool,
+    markdown_negotiation: bool,
+    language_variants: bool,
+    extensionless_urls: bool,
+    listing_validators: bool,
+    pagination: Option<&'a str>,
+    page_size: Option<usize>,
+    ranges: Vec<&'a str>,
+    encodings: &'a [&'a str],
+}
+
+/// Serialises the features enabled in the configuration as JSON
+///
+/// # Arguments
+/// * `config` - The configuration the server runs with
+///
+pub fn capabilities(config: &Config) -> String {
+    // Files served raw can always be requested in parts
+    let mut ranges = vec!["raw"];
+    if config.verbatim_ranges {
+        ranges.push("verbatim");
+    }
+    serde_json::to_string(&Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        json_listing: config.json_listing,
+        json_schema_versions: SCHEMA_VERSIONS,
+        json_errors: config.json_errors,
+        feeds: config.feeds,
+        zip_download: config.zip_download,
+        markdown_negotiation: config.markdown_negotiation,
+        language_variants: config.language_variants,
+        extensionless_urls: config.extensionless_urls,
+        listing_validators: config.listing_validators,
+        pagination: config.page_size.map(|_| match config.pagination {
+            Pagination::Offset => "offset",
+            Pagination::Cursor => "cursor",
+        }),
+        page_size: config.page_size,
+        ranges,
+        encodings: if config.gzip { &["gzip", "identity"] } else { &["identity"] },
+    }).unwrap_or_default()
+}
@@ -7,8 +7,11 @@
 //! which can be freely integerated within any application that uses `iron`.
 //!
 
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate rand;
 extern crate toml;
 extern crate url;
 extern crate iron;
@@ -17,14 +20,50 @@ extern crate mount;
 extern crate mime_guess;
 extern crate chrono;
 extern crate pulldown_cmark;
+extern crate flate2;
+#[cfg(test)]
+extern crate zip;
 
 mod config;
 mod entry;
+mod listing;
 mod renderer;
+mod markdown;
+mod negotiation;
+mod conditional;
+mod logging;
+mod walk;
+mod download;
+mod feed;
+mod json;
+mod throttle;
+mod pool;
+mod lru;
 mod archivist;
+#[cfg(test)]
+mod testing;
 
 pub use config::Config;
+pub use config::CachePolicy;
+pub use config::Rule;
+pub use config::LatestAlias;
+pub use config::AccessMethod;
+pub use config::NonUtf8Names;
+pub use config::Pagination;
+pub use config::ModifiedPrecision;
+pub use config::RequestIdScheme;
+pub use config::UrlForm;
+pub use config::PatternMatching;
+pub use config::SidecarFormat;
 pub use archivist::Archivist;
 pub use renderer::Renderer;
+pub use renderer::DefaultRenderer;
+pub use renderer::escape_html;
 pub use renderer::RenderResult;
+pub use renderer::RenderContext;
+pub use renderer::ListingPage;
 pub use entry::Entry;
+pub use listing::Listing;
+pub use listing::EntryOrder;
+pub use listing::TreeEntry;
+pub use logging::AccessEvent;
@@ -0,0 +1,626 @@
+use serde_json;
+use toml;
+use urlencoded::QueryMap;
+
+use std::cmp::Ordering;
+use std::ffi::OsString;
+use std::fs::{DirEntry, File, Metadata};
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use config::{Config, ModifiedPrecision, NonUtf8Names, SidecarFormat};
+use entry::{Entry, entry_with_metadata, name_bytes};
+use pool::Pool;
+
+/// Order in which the entries should be sorted
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum EntryOrder {
+    /// Sort the entries by their names
+    Lexicographical,
+    /// Sort the entries by their modification times
+    Chronological,
+    /// Sort the entries by their sizes
+    /// Directories are taken to be empty
+    BySize,
+}
+
+/// The entries in a directory, gathered for rendering
+///
+/// A `Listing` is built by [`Archivist::build_listing`](struct.Archivist.html#method.build_listing)
+/// from the query parameters of a request. It contains the entries to be rendered, along with how
+/// they were gathered.
+///
+#[derive(Debug, Clone)]
+pub struct Listing {
+    /// The entries in the directory, in the order they should be displayed
+    pub entries: Vec<Entry>,
+    /// The order in which the entries are sorted
+    /// If this is `None` then the entries are in the order given by the file system
+    pub order: Option<EntryOrder>,
+    /// Whether or not the entries are sorted in descending order
+    ///
+    /// This is only ever true if `order` is not `None`.
+    pub reversed: bool,
+    /// The value of the query parameter asking for the next page of the listing
+    ///
+    /// This is the `page` or the `cursor` parameter, according to `pagination` in the
+    /// configuration. If this is `None` then this is the last page.
+    pub next_page: Option<String>,
+    /// The value of the `page` parameter asking for the previous page of the listing
+    ///
+    /// This is always `None` for listings paginated by cursor, and for the first page.
+    pub prev_page: Option<String>,
+    /// The number of the page, counting from 1, and the number of pages in the listing
+    ///
+    /// This is only known for listings paginated by offset.
+    pub page: Option<(usize, usize)>,
+    /// The maximum number of entries on the page, or `None` if the listing is not paginated
+    pub page_size: Option<usize>,
+    /// The number of entries left out because they could not be read
+    ///
+    /// Entries may be removed, or become unreadable, while the listing is built.
+    pub skipped: usize,
+}
+
+/// An entry in a directory tree, along with the entries below it
+///
+/// Trees are passed to [`Renderer::render_dir_tree`](trait.Renderer.html#method.render_dir_tree)
+/// when `listing_tree_depth` in the configuration is greater than one. The `href` of each entry
+/// is relative to the directory being listed, rather than to the directory the entry is in.
+///
+#[derive(Debug, Clone)]
+pub struct TreeEntry {
+    /// The entry itself
+    pub entry: Entry,
+    /// The entries in the directory, in the order they should be displayed
+    ///
+    /// This is empty for files, and for directories at the depth of the tree.
+    pub children: Vec<TreeEntry>,
+    /// Whether or not some entries in the directory are missing from `children`
+    ///
+    /// This is always true for directories at the depth of the tree, as their entries are not
+    /// looked at, and for directories whose entries were cut off once the tree had
+    /// `listing_tree_max_entries` entries.
+    pub truncated: bool,
+}
+
+// Reads the order specified by the `order' query parameter
+//
+// Entries are not sorted chronologically if their modification times are hidden
+#[inline]
+pub fn entry_order(query: &QueryMap, config: &Config) -> Option<EntryOrder> {
+    query.get("order")
+        .and_then(|v| v.first())
+        .and_then(|o| match o.as_str() {
+            "lexicographical" => Some(EntryOrder::Lexicographical),
+            "chronological" if config.modified_precision != ModifiedPrecision::Hidden =>
+                Some(EntryOrder::Chronological),
+            "size" => Some(EntryOrder::BySize),
+            _ => None
+        } )
+}
+
+// Reads the direction specified by the `dir' query parameter
+//
+// Entries are sorted in ascending order unless `desc' is asked for
+#[inline]
+pub fn is_order_reversed(query: &QueryMap) -> bool {
+    query.get("dir")
+        .and_then(|v| v.first())
+        .map(String::as_str) == Some("desc")
+}
+
+// A directory entry along with its metadata, read once when the entry is gathered
+//
+// The entries are sorted by this metadata, so that they are not inspected again
+pub struct Gathered {
+    pub file_name: OsString,
+    pub path: PathBuf,
+    // None if the metadata cannot be read
+    pub metadata: Option<Metadata>,
+}
+
+impl Gathered {
+    pub fn file_name(&self) -> OsString {
+        self.file_name.clone()
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.metadata.as_ref().map(Metadata::is_dir).unwrap_or(false)
+    }
+
+    // The modification time of the entry, if it can be read
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.metadata.as_ref().and_then(|md| md.modified().ok())
+    }
+
+    // The size of the file, or 0 for a directory or an entry that cannot be read
+    pub fn size(&self) -> u64 {
+        match self.metadata {
+            Some(ref md) if !md.is_dir() => md.len(),
+            _ => 0,
+        }
+    }
+}
+
+// Collects the directory entries that can be accessed and are not hidden,
+// each along with the entry object built from it
+//
+// Entries are inspected on the threads of the pool if there is one,
+// but they are kept in the order they were given in
+//
+// Returns an error if the entries could not all be inspected
+pub fn gather_entries(config: &Arc<Config>, pool: Option<&Pool>, dir_entries: Vec<DirEntry>)
+        -> io::Result<Vec<(Gathered, io::Result<Entry>)>> {
+    let pool = match pool {
+        Some(pool) if dir_entries.len() > 1 => pool,
+        _ => return Ok(dir_entries.into_iter()
+            .filter_map(|e| gather_entry(config, e))
+            .collect()),
+    };
+
+    // Deal the entries out to the threads
+    let threads = pool.threads();
+    let mut chunks : Vec<Vec<DirEntry>> = (0..threads)
+        .map(|_| Vec::new())
+        .collect();
+    let chunk_size = dir_entries.len().div_ceil(threads);
+    for (i, e) in dir_entries.into_iter().enumerate() {
+        chunks[i / chunk_size].push(e);
+    }
+    let jobs : Vec<_> = chunks.into_iter()
+        .filter(|chunk| !chunk.is_empty())
+        .map(|chunk| {
+            let config = config.clone();
+            move || chunk.into_iter()
+                .filter_map(|e| gather_entry(&config, e))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    // The chunks come back in order
+    Ok(pool.run_all(jobs)?.into_iter().flatten().collect())
+}
+
+fn gather_entry(config: &Config, e: DirEntry) -> Option<(Gathered, io::Result<Entry>)> {
+    let path = e.path();
+    if config.method_for(&path).unwrap_or(None).is_none()
+            || config.time_until_written(&path).is_some() {
+        return None;
+    }
+    let gathered = Gathered {
+        file_name: e.file_name(),
+        path,
+        metadata: e.metadata().ok(),
+    };
+    let entry = entry_for(config, &gathered)?;
+    Some((gathered, entry))
+}
+
+// Builds the entry object for a servable entry, as configured
+// Returns None if the entry is not to be listed
+//
+// Returns an error in place of the entry if it cannot be read,
+// or if its name is not UTF-8 and the configuration says to fail the listing then
+pub fn entry_for(config: &Config, gathered: &Gathered) -> Option<io::Result<Entry>> {
+    if config.is_hidden_in_listing(&gathered.path) {
+        return None;
+    }
+    let is_utf8 = gathered.file_name.to_str().is_some();
+    let entry = match (config.non_utf8_names, gathered.metadata.as_ref()) {
+        (NonUtf8Names::Skip, _) if !is_utf8 => return None,
+        (NonUtf8Names::Error, _) if !is_utf8 => Err(io::Error::other(
+            "File name is not valid UTF-8."
+        )),
+        (_, Some(md)) => entry_with_metadata(gathered.file_name(), md),
+        (_, None) => Err(io::Error::other("The metadata of the entry cannot be read.")),
+    };
+    Some(entry.map(|mut entry| {
+        if config.strip_extensions_in_listing && !entry.is_dir {
+            entry.display_name = display_name(&entry.file_name, config);
+        }
+        if !config.expose_permissions {
+            entry.mode = None;
+        }
+        entry.with_precision(config.modified_precision)
+    }))
+}
+
+// The name of a file without its extension, if the extension is to be left out
+fn display_name(file_name: &str, config: &Config) -> String {
+    let path = Path::new(file_name);
+    match (path.file_stem().and_then(|s| s.to_str()), path.extension()) {
+        (Some(stem), Some(ext)) if config.stripped_extensions.is_empty()
+                || config.stripped_extensions.contains(ext) => String::from(stem),
+        _ => String::from(file_name),
+    }
+}
+
+// Sorts the directory entries in the specified order
+// Entries that are not sorted are never reversed
+pub fn sort_entries<T>(
+    entries: &mut [(Gathered, T)],
+    order: Option<EntryOrder>,
+    reversed: bool,
+) {
+    match order {
+        Some(EntryOrder::Lexicographical) =>
+            entries.sort_by(|e1, e2| cmp_entry_by_name(&e1.0, &e2.0)),
+
+        Some(EntryOrder::Chronological) => 
+            entries.sort_by(|e1, e2| cmp_entry_by_modified(&e1.0, &e2.0)),
+
+        Some(EntryOrder::BySize) =>
+            entries.sort_by_key(|e| e.0.size()),
+
+        None => return,
+    }
+    if reversed {
+        entries.reverse();
+    }
+}
+
+// Moves the directories in front of the files
+// The entries stay in their order otherwise
+pub fn group_dirs_first<T>(entries: &mut [(Gathered, T)]) {
+    entries.sort_by_key(|e| !e.0.is_dir());
+}
+
+// Moves the pinned entries to the front, in the order they are pinned in
+// The other entries stay in their order
+pub fn pin_entries<T>(entries: &mut Vec<(Gathered, T)>, pinned: &[OsString]) {
+    if pinned.is_empty() {
+        return;
+    }
+    let mut rest = Vec::with_capacity(entries.len());
+    let mut front : Vec<Option<(Gathered, T)>> = pinned.iter().map(|_| None).collect();
+    for e in entries.drain(..) {
+        match pinned.iter().position(|p| *p == e.0.file_name()) {
+            Some(i) => front[i] = Some(e),
+            None => rest.push(e),
+        }
+    }
+    entries.extend(front.into_iter().flatten());
+    entries.extend(rest);
+}
+
+// Reads the names of the entries listed in the order file of the directory, one per line
+// Returns None if the order file is not configured or cannot be read
+pub fn read_order_file(dir: &Path, config: &Config) -> Option<Vec<OsString>> {
+    let order_file = config.order_file.as_ref()?;
+    let mut content = String::new();
+    File::open(dir.join(order_file)).ok()?
+        .read_to_string(&mut content).ok()?;
+    Some(content.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .map(OsString::from)
+        .collect())
+}
+
+// Sorts the entries by their weights, lightest first
+// Entries without weights follow, and entries of equal weights stay in their order
+pub fn sort_by_weight<T, F>(entries: &mut Vec<(Gathered, T)>, mut weight_of: F)
+        where F: FnMut(&Gathered) -> Option<f64> {
+    let mut weighted : Vec<(Option<f64>, (Gathered, T))> = entries.drain(..)
+        .map(|e| (weight_of(&e.0), e))
+        .collect();
+    weighted.sort_by(|w1, w2| match (w1.0, w2.0) {
+        (Some(w1), Some(w2)) => w1.partial_cmp(&w2).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+    entries.extend(weighted.into_iter().map(|w| w.1));
+}
+
+// The path to the sidecar of the file at the path
+// Returns None if sidecars are not configured
+pub fn sidecar_path(path: &Path, config: &Config) -> Option<PathBuf> {
+    let extension = config.sidecar_extension.as_ref()?;
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(extension);
+    Some(PathBuf::from(sidecar))
+}
+
+// Reads the weight of an entry from its sidecar
+// Returns None if the sidecar cannot be read, or has no weight that is a finite number
+pub fn read_sidecar_weight(sidecar: &Path, config: &Config) -> Option<f64> {
+    let mut content = String::new();
+    File::open(sidecar).ok()?
+        .read_to_string(&mut content).ok()?;
+    let field = config.sidecar_weight_field.as_str();
+    let weight = match config.sidecar_format {
+        SidecarFormat::Toml => {
+            let value = content.parse::<toml::Value>().ok()?;
+            let weight = value.get(field)?;
+            weight.as_float().or_else(|| weight.as_integer().map(|w| w as f64))?
+        },
+        SidecarFormat::Json => serde_json::from_str::<serde_json::Value>(&content).ok()?
+            .get(field)?
+            .as_f64()?,
+    };
+    if weight.is_finite() {
+        Some(weight)
+    } else {
+        None
+    }
+}
+
+// The number of entries per page asked for by the `per_page' query parameter
+// Numbers larger than allowed are lowered rather than refused
+//
+// Returns None if listings are not split into pages
+pub fn page_size_for(query: &QueryMap, config: &Config) -> Option<usize> {
+    let default = config.page_size?;
+    let max = config.max_page_size.unwrap_or(default).max(default);
+    Some(query.get("per_page")
+        .and_then(|v| v.first())
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or(default)
+        .max(1)
+        .min(max))
+}
+
+// Keeps only the numbered page asked for by the `page' query parameter
+// Pages are numbered from 1, and the last page is kept for numbers past the end
+//
+// Returns the number of the page along with the number of pages
+pub fn page_by_offset<T>(entries: &mut Vec<(Gathered, T)>, query: &QueryMap, page_size: usize)
+        -> (usize, usize) {
+    let pages = entries.len().div_ceil(page_size).max(1);
+    let page = query.get("page")
+        .and_then(|v| v.first())
+        .and_then(|p| p.parse::<usize>().ok())
+        .unwrap_or(1)
+        .max(1)
+        .min(pages);
+    entries.drain(..(page - 1) * page_size);
+    entries.truncate(page_size);
+    (page, pages)
+}
+
+// Sorts the entries and keeps only the page following the `cursor' query parameter
+// The pinned entries only head the first page
+//
+// Returns the cursor to the next page if there is one
+pub fn page_by_cursor<T>(
+    entries: &mut Vec<(Gathered, T)>,
+    query: &QueryMap,
+    order: Option<EntryOrder>,
+    reversed: bool,
+    dirs_first: bool,
+    page_size: usize,
+    pinned: &[OsString],
+) -> Option<String> {
+    let mut keyed : Vec<(Gathered, (CursorKey, T))> = entries.drain(..)
+        .map(|(de, t)| {
+            let key = CursorKey::of(&de, order, dirs_first);
+            (de, (key, t))
+        })
+        .collect();
+    keyed.sort_by(|e1, e2| (e1.1).0.cmp_in(&(e2.1).0, reversed));
+
+    // A cursor that cannot be read leads back to the first page
+    let cursor = query.get("cursor")
+        .and_then(|v| v.first())
+        .and_then(|c| CursorKey::decode(c, order, dirs_first));
+    match cursor {
+        Some(ref cursor) => keyed.retain(|e|
+            (e.1).0.cmp_in(cursor, reversed) == Ordering::Greater
+                && !pinned.contains(&e.0.file_name())
+        ),
+        None => pin_entries(&mut keyed, pinned),
+    }
+
+    let more = keyed.len() > page_size;
+    keyed.truncate(page_size);
+    // The next page follows the last entry on this page that is not pinned
+    let next = if more {
+        let last = keyed.iter().rev()
+            .find(|e| !pinned.contains(&e.0.file_name()))
+            .map(|e| (e.1).0.clone())
+            .unwrap_or_else(|| CursorKey::start(order, dirs_first, reversed));
+        Some(last.encode())
+    } else {
+        None
+    };
+    entries.extend(keyed.into_iter().map(|(de, (_, t))| (de, t)));
+    next
+}
+
+// The position of an entry in a listing paginated by cursor
+//
+// If directories are listed first, entries are compared by whether they are files first.
+// Entries sorted chronologically are then compared by their modification times,
+// and entries sorted by size by their sizes.
+// All entries are then compared by their names, so that no two entries share a position.
+// Names are compared naturally, as when sorting by name.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct CursorKey {
+    is_file: Option<bool>,
+    modified: Option<Duration>,
+    size: Option<u64>,
+    name: Vec<u8>,
+}
+
+impl CursorKey {
+    fn of(e: &Gathered, order: Option<EntryOrder>, dirs_first: bool) -> CursorKey {
+        let is_file = if dirs_first {
+            Some(!e.is_dir())
+        } else {
+            None
+        };
+        let modified = match order {
+            Some(EntryOrder::Chronological) => Some(
+                e.modified()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .unwrap_or_default()
+            ),
+            _ => None,
+        };
+        let size = match order {
+            Some(EntryOrder::BySize) => Some(e.size()),
+            _ => None,
+        };
+        CursorKey {
+            is_file,
+            modified,
+            size,
+            name: name_bytes(&e.file_name()),
+        }
+    }
+
+    // Compares the positions of the keys in a listing sorted in the specified direction
+    //
+    // Directories listed first stay first in descending order
+    fn cmp_in(&self, other: &CursorKey, reversed: bool) -> Ordering {
+        let rest = (&self.modified, &self.size).cmp(&(&other.modified, &other.size))
+            .then_with(|| cmp_names(&self.name, &other.name));
+        self.is_file.cmp(&other.is_file)
+            .then(if reversed { rest.reverse() } else { rest })
+    }
+
+    // The position before all entries in a listing sorted in the specified direction
+    //
+    // In descending order no file name can be as long as the name of this key
+    fn start(order: Option<EntryOrder>, dirs_first: bool, reversed: bool) -> CursorKey {
+        let (modified, size, name) = if reversed {
+            (Duration::new(u64::MAX, 999_999_999), u64::MAX, vec![0xff; 256])
+        } else {
+            (Duration::default(), 0, Vec::new())
+        };
+        CursorKey {
+            is_file: if dirs_first { Some(false) } else { None },
+            modified: match order {
+                Some(EntryOrder::Chronological) => Some(modified),
+                _ => None,
+            },
+            size: match order {
+                Some(EntryOrder::BySize) => Some(size),
+                _ => None,
+            },
+            name,
+        }
+    }
+
+    // Encodes the key using only characters that are safe in urls
+    //
+    // The name is written in hex, and is preceded by the seconds and
+    // nanoseconds of the modification time if the key has one,
+    // or by the size if the key has one.
+    // All of these are preceded by `d' or `f' if the key is grouped into directories and files.
+    fn encode(&self) -> String {
+        let name : String = self.name.iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let position = match (self.modified, self.size) {
+            (Some(m), _) => format!("{}.{}.{}", m.as_secs(), m.subsec_nanos(), name),
+            (None, Some(size)) => format!("{}.{}", size, name),
+            (None, None) => name,
+        };
+        match self.is_file {
+            Some(true) => format!("f.{}", position),
+            Some(false) => format!("d.{}", position),
+            None => position,
+        }
+    }
+
+    // Decodes a key for entries sorted in the specified order
+    // Returns None if the cursor is malformed or meant for another order
+    fn decode(cursor: &str, order: Option<EntryOrder>, dirs_first: bool) -> Option<CursorKey> {
+        let (is_file, cursor) = match (cursor.strip_prefix("f."), cursor.strip_prefix("d.")) {
+            (Some(rest), _) => (Some(true), rest),
+            (None, Some(rest)) => (Some(false), rest),
+            (None, None) => (None, cursor),
+        };
+        let parts : Vec<&str> = cursor.split('.').collect();
+        let (modified, size, name) = match parts.len() {
+            1 => (None, None, parts[0]),
+            2 => (None, Some(parts[0].parse().ok()?), parts[1]),
+            3 => {
+                let secs = parts[0].parse().ok()?;
+                let nanos = parts[1].parse().ok().filter(|&n| n < 1_000_000_000)?;
+                (Some(Duration::new(secs, nanos)), None, parts[2])
+            },
+            _ => return None,
+        };
+        if is_file.is_some() != dirs_first
+                || modified.is_some() != (order == Some(EntryOrder::Chronological))
+                || size.is_some() != (order == Some(EntryOrder::BySize)) {
+            return None;
+        }
+        let name = name.as_bytes()
+            .chunks(2)
+            .map(|c| str::from_utf8(c).ok()
+                .filter(|c| c.len() == 2)
+                .and_then(|c| u8::from_str_radix(c, 16).ok()))
+            .collect::<Option<Vec<u8>>>()?;
+        Some(CursorKey {
+            is_file,
+            modified,
+            size,
+            name,
+        })
+    }
+}
+
+// Comparers for gathered entries
+fn cmp_entry_by_name(e1: &Gathered, e2: &Gathered) -> Ordering {
+    cmp_names(&name_bytes(&e1.file_name()), &name_bytes(&e2.file_name()))
+}
+
+// Compares the names naturally, so that runs of digits are compared by their values
+// Thus `file2' comes before `file10'
+//
+// Names that are still equal, such as `file2' and `file02', are compared by their bytes
+fn cmp_names(n1: &[u8], n2: &[u8]) -> Ordering {
+    let (mut i, mut j) = (0, 0);
+    while i < n1.len() && j < n2.len() {
+        if n1[i].is_ascii_digit() && n2[j].is_ascii_digit() {
+            let (run1, run2) = (digit_run(&n1[i..]), digit_run(&n2[j..]));
+            let (v1, v2) = (without_leading_zeros(run1), without_leading_zeros(run2));
+            let ordering = v1.len().cmp(&v2.len()).then(v1.cmp(v2));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            i += run1.len();
+            j += run2.len();
+        } else {
+            if n1[i] != n2[j] {
+                return n1[i].cmp(&n2[j]);
+            }
+            i += 1;
+            j += 1;
+        }
+    }
+    (n1.len() - i).cmp(&(n2.len() - j)).then(n1.cmp(n2))
+}
+
+// The run of digits at the start of the name
+fn digit_run(name: &[u8]) -> &[u8] {
+    let length = name.iter().take_while(|b| b.is_ascii_digit()).count();
+    &name[..length]
+}
+
+fn without_leading_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|&&b| b == b'0').count();
+    &digits[zeros..]
+}
+
+// Entries whose modification times cannot be read are taken to be equal to any other
+fn cmp_entry_by_modified(e1: &Gathered, e2: &Gathered) -> Ordering {
+    match (e1.modified(), e2.modified()) {
+        (Some(m1), Some(m2)) => m1.cmp(&m2),
+        _ => Ordering::Equal,
+    }
+}
@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+
+use config::AccessMethod;
+
+/// A record of a request handled by an `Archivist`
+///
+/// An `AccessEvent` is passed to the callback given to
+/// [`Archivist::on_access`](struct.Archivist.html#method.on_access) after each request.
+///
+#[derive(Debug, Clone, Default)]
+pub struct AccessEvent {
+    /// The path requested, relative to the served root
+    pub path: String,
+    /// The HTTP status code of the response
+    pub status: Option<u16>,
+    /// How the requested file or directory was accessed
+    /// This is `None` if the request did not reach an archive
+    pub method: Option<AccessMethod>,
+    /// The path of the scoped rule applying to the requested archive, if any
+    pub rule: Option<PathBuf>,
+    /// Whether or not the representation served was chosen by content negotiation
+    pub negotiated: bool,
+    /// The ID of the request, either given by the client or generated
+    ///
+    /// This is only set if `request_id_header` is set in the configuration.
+    pub request_id: Option<String>,
+    /// The number of entries left out of the listing because they could not be read
+    pub skipped_entries: usize,
+    /// The content coding the response was compressed with, such as `gzip`
+    /// This is `None` if the response was sent uncompressed
+    pub encoding: Option<String>,
+}
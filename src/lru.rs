@@ -0,0 +1,79 @@
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// A map holding at most a fixed number of entries
+///
+/// Once the map is full, the entry used least recently is thrown away to make room for a new one.
+///
+pub struct Lru<K, V> {
+    capacity: usize,
+    // Each value along with when it was last used
+    entries: HashMap<K, (V, u64)>,
+    // The keys of the entries, by when they were last used
+    uses: BTreeMap<u64, K>,
+    clock: u64,
+}
+
+impl<K, V> Lru<K, V> where K: Hash + Eq + Clone {
+    /// Builds an empty map holding at most the specified number of entries
+    ///
+    /// # Arguments
+    /// * `capacity` - The number of entries, which is raised to one if it is zero
+    ///
+    pub fn new(capacity: usize) -> Lru<K, V> {
+        Lru {
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            uses: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Returns the value of the key, marking it as used
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+            where K: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.clock += 1;
+        let clock = self.clock;
+        let entry = self.entries.get_mut(key)?;
+        if let Some(k) = self.uses.remove(&entry.1) {
+            self.uses.insert(clock, k);
+        }
+        entry.1 = clock;
+        Some(&entry.0)
+    }
+
+    /// Sets the value of the key, throwing away the entry used least recently if there is no room
+    pub fn insert(&mut self, key: K, value: V) {
+        self.clock += 1;
+        if let Some((_, used)) = self.entries.remove(&key) {
+            self.uses.remove(&used);
+        } else if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.uses.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.uses.insert(self.clock, key.clone());
+        self.entries.insert(key, (value, self.clock));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_used_least_recently_are_thrown_away() {
+        let mut lru = Lru::new(2);
+        lru.insert("a", 1);
+        lru.insert("b", 2);
+        assert_eq!(lru.get("a"), Some(&1));
+        lru.insert("c", 3);
+        assert_eq!(lru.get("b"), None);
+        assert_eq!(lru.get("a"), Some(&1));
+        lru.insert("a", 4);
+        lru.insert("d", 5);
+        assert_eq!(lru.get("c"), None);
+        assert_eq!(lru.get("a"), Some(&4));
+    }
+}
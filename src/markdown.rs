@@ -0,0 +1,92 @@
+use pulldown_cmark::{html, Event, Parser, Tag};
+
+use config::Config;
+
+/// Renders the Markdown script to HTML
+///
+/// # Arguments
+/// * `content` - The Markdown script
+/// * `config`  - The configuration specifying how the script should be rendered
+///
+pub fn render(content: &str, config: &Config) -> String {
+    let offset = config.markdown_heading_offset as i32;
+    let parser = Parser::new(content).map(|event| match event {
+        // Shift the headings so that they nest under the headings of the page
+        Event::Start(Tag::Header(level)) =>
+            Event::Start(Tag::Header(shift_heading(level, offset))),
+        Event::End(Tag::Header(level)) =>
+            Event::End(Tag::Header(shift_heading(level, offset))),
+        _ => event,
+    });
+    let mut result = String::new();
+    html::push_html(&mut result, parser);
+    result
+}
+
+// There is nothing below `<h6>' in HTML
+#[inline]
+fn shift_heading(level: i32, offset: i32) -> i32 {
+    (level + offset).min(6)
+}
+
+/// Finds the local resources referenced by the rendered page, such as images and style sheets
+///
+/// These are the relative urls in `src` attributes, and in `href` attributes of `<link>` tags.
+/// Urls with a scheme or a host, absolute paths and fragments are left out. Each url is found
+/// once, with its fragment removed.
+///
+/// # Arguments
+/// * `html` - The rendered page
+///
+pub fn local_assets(html: &str) -> Vec<String> {
+    let mut assets = Vec::new();
+    for tag in html.split('<').skip(1) {
+        let tag = match tag.find('>') {
+            Some(end) => &tag[..end],
+            None => continue,
+        };
+        let name = tag.split_whitespace().next().unwrap_or("").to_lowercase();
+        let url = match attribute(tag, "src") {
+            Some(url) => url,
+            None if name == "link" => match attribute(tag, "href") {
+                Some(url) => url,
+                None => continue,
+            },
+            None => continue,
+        };
+        let url = url.replace("&amp;", "&");
+        let url = url.split('#').next().unwrap_or("");
+        if is_local(url) && !assets.iter().any(|a| a == url) {
+            assets.push(String::from(url));
+        }
+    }
+    assets
+}
+
+// The value of the attribute in the tag, if it is quoted
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(at) = rest.find(name) {
+        let before = rest[..at].chars().last();
+        let after = &rest[at + name.len()..];
+        rest = after;
+        if !before.is_some_and(|c| c.is_whitespace()) || !after.starts_with('=') {
+            continue;
+        }
+        let value = &after[1..];
+        let quote = value.chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+        let value = &value[1..];
+        return value.find(quote).map(|end| &value[..end]);
+    }
+    None
+}
+
+// Whether or not the url is relative to the page,
+// that is without a scheme or a host, and not an absolute path
+fn is_local(url: &str) -> bool {
+    let before_path = url.split('/').next().unwrap_or("");
+    !url.is_empty() && !url.starts_with('/') && !before_path.contains(':')
+}
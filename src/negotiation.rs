@@ -0,0 +1,171 @@
+use iron::prelude::*;
+use iron::headers::{Accept, AcceptEncoding, Encoding};
+use iron::mime::Mime;
+
+/// Picks the media type most preferred by the client
+/// Returns None if the client does not state a preference, or if none of the candidates is
+/// acceptable
+///
+/// Ties are broken by the order of the candidates.
+///
+/// # Arguments
+/// * `req`        - The request stating the preference in its `Accept` header
+/// * `candidates` - The media types that can be served, such as `text/html`
+///
+pub fn preferred_type<'a>(req: &Request, candidates: &[&'a str]) -> Option<&'a str> {
+    let accept = req.headers.get::<Accept>()?;
+    let mut preferred = None;
+    let mut best_quality = 0;
+    for &candidate in candidates {
+        let quality = accepted_quality(accept, candidate);
+        if quality > best_quality {
+            preferred = Some(candidate);
+            best_quality = quality;
+        }
+    }
+    preferred
+}
+
+// The quality with which the media type is accepted
+//
+// The most specific media range matching the type decides its quality
+fn accepted_quality(accept: &Accept, media_type: &str) -> u16 {
+    let mut parts = media_type.splitn(2, '/');
+    let top = parts.next().unwrap_or("");
+    let sub = parts.next().unwrap_or("");
+
+    let mut specificity = 0;
+    let mut quality = 0;
+    for q in accept.iter() {
+        let Mime(ref q_top, ref q_sub, _) = q.item;
+        let (q_top, q_sub) = (q_top.to_string(), q_sub.to_string());
+        let s = if q_top == top && q_sub == sub {
+            3
+        } else if q_top == top && q_sub == "*" {
+            2
+        } else if q_top == "*" && q_sub == "*" {
+            1
+        } else {
+            continue
+        };
+        if s > specificity {
+            specificity = s;
+            quality = q.quality.0;
+        }
+    }
+    quality
+}
+
+/// Picks the language most preferred by the client
+/// Returns None if the client does not state a preference, or if none of the candidates is
+/// acceptable
+///
+/// A language range such as `fr` matches the tags below it, such as `fr-CH`. A tag such as `fr`
+/// is also taken for the ranges below it, less specifically. Ties are broken by the order of the
+/// candidates.
+///
+/// # Arguments
+/// * `req`        - The request stating the preference in its `Accept-Language` header
+/// * `candidates` - The language tags that can be served, such as `en`
+///
+pub fn preferred_language<'a>(req: &Request, candidates: &[&'a str]) -> Option<&'a str> {
+    let ranges : Vec<(String, u16)> = req.headers.get_raw("Accept-Language")?
+        .iter()
+        .flat_map(|v| String::from_utf8_lossy(v)
+            .split(',')
+            .filter_map(language_range)
+            .collect::<Vec<_>>())
+        .collect();
+    let mut preferred = None;
+    let mut best_quality = 0;
+    for &candidate in candidates {
+        let quality = language_quality(&ranges, candidate);
+        if quality > best_quality {
+            preferred = Some(candidate);
+            best_quality = quality;
+        }
+    }
+    preferred
+}
+
+// Reads a language range such as `fr;q=0.8' along with its quality in thousandths
+fn language_range(item: &str) -> Option<(String, u16)> {
+    let mut parts = item.split(';');
+    let range = parts.next()?.trim().to_lowercase();
+    if range.is_empty() {
+        return None;
+    }
+    let mut quality = 1000;
+    for param in parts {
+        let mut param = param.splitn(2, '=');
+        if param.next().map(|p| p.trim()) == Some("q") {
+            let q : f32 = param.next()?.trim().parse().ok()?;
+            quality = (q.clamp(0.0, 1.0) * 1000.0) as u16;
+        }
+    }
+    Some((range, quality))
+}
+
+// The quality with which the language is accepted
+//
+// The most specific language range matching the tag decides its quality
+fn language_quality(ranges: &[(String, u16)], tag: &str) -> u16 {
+    let tag = tag.to_lowercase();
+    let mut specificity = 0;
+    let mut quality = 0;
+    for &(ref range, q) in ranges {
+        let s = if *range == tag {
+            4
+        } else if tag.starts_with(&format!("{}-", range)) {
+            3
+        } else if range.starts_with(&format!("{}-", tag)) {
+            2
+        } else if range == "*" {
+            1
+        } else {
+            continue
+        };
+        if s > specificity {
+            specificity = s;
+            quality = q;
+        }
+    }
+    quality
+}
+
+/// Whether or not the client accepts responses compressed with gzip
+///
+/// Only an explicit `gzip` in the `Accept-Encoding` header counts, unless its quality is zero.
+///
+/// # Arguments
+/// * `req` - The request stating the encodings it accepts
+///
+pub fn accepts_gzip(req: &Request) -> bool {
+    match req.headers.get::<AcceptEncoding>() {
+        Some(accept) => accept.iter().any(|q| q.item == Encoding::Gzip && q.quality.0 > 0),
+        None => false,
+    }
+}
+
+/// Adds a request header to the `Vary` header of the response
+///
+/// # Arguments
+/// * `res`    - The response
+/// * `header` - The name of the request header that the response depends on
+///
+pub fn add_vary(res: &mut Response, header: &str) {
+    let value = match res.headers.get_raw("Vary") {
+        Some(values) => {
+            let current = values.iter()
+                .map(|v| String::from_utf8_lossy(v).into_owned())
+                .collect::<Vec<String>>()
+                .join(", ");
+            if current.split(',').any(|h| h.trim().eq_ignore_ascii_case(header)) {
+                return;
+            }
+            format!("{}, {}", current, header)
+        },
+        None => String::from(header),
+    };
+    res.headers.set_raw("Vary", vec![value.into_bytes()]);
+}
@@ -0,0 +1,107 @@
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of threads to run jobs on
+///
+/// The threads are started along with the pool, and stop once the pool is dropped. A job that
+/// panics does not take its thread down with it.
+///
+pub struct Pool {
+    threads: usize,
+    jobs: Mutex<Sender<Job>>,
+}
+
+impl Pool {
+    /// Starts a pool with the specified number of threads
+    ///
+    /// # Arguments
+    /// * `threads` - The number of threads, which is raised to one if it is zero
+    ///
+    pub fn new(threads: usize) -> Pool {
+        let threads = threads.max(1);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..threads {
+            let receiver = receiver.clone();
+            thread::spawn(move || loop {
+                // The lock is let go before the job is run
+                // The receiver is only ever disconnected once the pool is dropped
+                let job = match receiver.lock().ok().and_then(|r| r.recv().ok()) {
+                    Some(job) => job,
+                    None => return,
+                };
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            });
+        }
+        Pool {
+            threads,
+            jobs: Mutex::new(sender),
+        }
+    }
+
+    /// The number of threads in the pool
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Runs the jobs on the threads of the pool, and waits for all of them to finish
+    ///
+    /// The results are in the order the jobs were given in.
+    ///
+    /// # Error
+    /// Returns an error if any of the jobs panicked.
+    ///
+    pub fn run_all<F, R>(&self, jobs: Vec<F>) -> io::Result<Vec<R>>
+            where F: FnOnce() -> R + Send + 'static, R: Send + 'static {
+        let count = jobs.len();
+        let (sender, receiver) = mpsc::channel();
+        {
+            let queue = self.jobs.lock().map_err(|_| failed())?;
+            for (i, job) in jobs.into_iter().enumerate() {
+                let sender = sender.clone();
+                queue.send(Box::new(move || {
+                    let _ = sender.send((i, job()));
+                })).map_err(|_| failed())?;
+            }
+        }
+        // A job that panics drops its sender without sending anything
+        drop(sender);
+        let mut results : Vec<(usize, R)> = receiver.iter().collect();
+        if results.len() < count {
+            return Err(failed());
+        }
+        results.sort_by_key(|&(i, _)| i);
+        Ok(results.into_iter().map(|(_, r)| r).collect())
+    }
+}
+
+fn failed() -> io::Error {
+    io::Error::other("A job in the pool failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_are_in_order_and_panics_are_errors() {
+        let pool = Pool::new(3);
+        let jobs : Vec<_> = (0..10).map(|i| move || i * 2).collect();
+        assert_eq!(pool.run_all(jobs).unwrap(), (0..10).map(|i| i * 2).collect::<Vec<_>>());
+
+        let jobs : Vec<Box<dyn FnOnce() -> i32 + Send>> = vec![
+            Box::new(|| 1),
+            Box::new(|| panic!("while inspecting")),
+        ];
+        assert!(pool.run_all(jobs).is_err());
+
+        // The threads survive the panic
+        let jobs : Vec<_> = (0..3).map(|i| move || i).collect();
+        assert_eq!(pool.run_all(jobs).unwrap(), vec![0, 1, 2]);
+    }
+}
@@ -10,55 +10,353 @@ use std::error;
 */
 
 use entry::Entry;
+use listing::TreeEntry;
+
+use chrono::{DateTime, Utc};
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
 /// A type alias for the return type of renderer methods
 pub type RenderResult = Result<String, IronError>;
 
+/// Information about the request being rendered
+///
+/// A `RenderContext` is passed to every method of a [`Renderer`](trait.Renderer.html).
+///
+#[derive(Debug, Clone)]
+pub struct RenderContext {
+    /// The path to the requested file or directory, relative to the served root
+    pub path: String,
+    /// The resolved absolute path to the requested file or directory in the file system
+    ///
+    /// This is only provided when `expose_fs_paths` is turned on in the configuration, as it
+    /// reveals the layout of the server's file system.
+    pub fs_path: Option<PathBuf>,
+    /// The query parameters passed through to the renderer, such as `theme`
+    ///
+    /// Only the parameters listed in `passthrough_params` in the configuration are included.
+    /// Only the first value of each parameter is kept.
+    pub params: BTreeMap<String, String>,
+    /// The nonce allowed by the `Content-Security-Policy` header of the response
+    ///
+    /// Inline scripts and styles tagged with `nonce="..."` are allowed to run. This is only
+    /// provided when `content_security_policy` is set in the configuration, and is different for
+    /// every response.
+    pub csp_nonce: Option<String>,
+    /// The version of the archivist serving the page, such as `0.1.0`
+    pub server_version: &'static str,
+    /// The time at which the page is served
+    pub time: DateTime<Utc>,
+    /// Where the page is among the pages of the listing
+    ///
+    /// This is only provided for listings when `page_size` is set in the configuration.
+    pub page: Option<ListingPage>,
+}
+
+impl Default for RenderContext {
+    fn default() -> Self {
+        RenderContext {
+            path: String::new(),
+            fs_path: None,
+            params: BTreeMap::new(),
+            csp_nonce: None,
+            server_version: env!("CARGO_PKG_VERSION"),
+            time: Utc::now(),
+            page: None,
+        }
+    }
+}
+
+/// Where a page of a directory listing is among the pages of the whole listing
+///
+/// The urls of the other pages are relative to the listing, such as `?page=2`. The rest of the
+/// query, such as the order, is kept in them.
+///
+#[derive(Debug, Clone)]
+pub struct ListingPage {
+    /// The number of the page, counting from 1
+    ///
+    /// This is only known for listings paginated by offset.
+    pub number: Option<usize>,
+    /// The number of pages in the listing
+    ///
+    /// This is only known for listings paginated by offset.
+    pub count: Option<usize>,
+    /// The maximum number of entries on the page
+    pub size: usize,
+    /// The url of the previous page, or `None` for the first page
+    ///
+    /// This is always `None` for listings paginated by cursor.
+    pub prev: Option<String>,
+    /// The url of the next page, or `None` for the last page
+    pub next: Option<String>,
+}
+
 /// A renderer that renders the webpage in the response
 ///
 /// An implementation is provided for
 /// [tera::Tera](https://docs.rs/tera/0.10.10/tera/struct.Tera.html).
 /// Turn on the `tera` feature to use this implementation.
 ///
-/// See `examples/simple.rs` for a minimal implementation of the renderer.
+/// See `examples/simple.rs` for a minimal implementation of the renderer, or use the
+/// [`DefaultRenderer`](struct.DefaultRenderer.html) as it is.
 ///
 pub trait Renderer {
     /// Renders the list of entries in a directory.
     ///
     /// # Arguments
-    /// * `path_str` - The path to the specified directory as an `str` slice
-    /// * `entries`  - The entries in the specified path
+    /// * `context` - The context of the request to the specified directory
+    /// * `entries` - The entries in the specified path
+    ///
+    fn render_dir(&self, context: &RenderContext, entries: &[Entry]) -> RenderResult;
+
+    /// Renders the entries in a directory as a tree, a few levels deep.
+    ///
+    /// This is used instead of `render_dir` when `listing_tree_depth` is greater than one in the
+    /// configuration.
+    ///
+    /// By default, only the entries in the directory itself are rendered by `render_dir`.
+    ///
+    /// # Arguments
+    /// * `context` - The context of the request to the specified directory
+    /// * `tree`    - The entries in the specified path, each with the entries below it
     ///
-    fn render_dir(&self, path_str: &str, entries: &[Entry]) -> RenderResult;
+    fn render_dir_tree(&self, context: &RenderContext, tree: &[TreeEntry]) -> RenderResult {
+        let entries : Vec<Entry> = tree.iter()
+            .map(|t| t.entry.clone())
+            .collect();
+        self.render_dir(context, &entries)
+    }
     
     /// Renders the unmodified textual content of a file.
     ///
     /// # Arguments
-    /// * `path_str` - The path to the specified file as an `str` slice
-    /// * `entries`  - The textual content of the file
+    /// * `context` - The context of the request to the specified file
+    /// * `content` - The textual content of the file
     ///
-    fn render_verbatim(&self, path_str: &str, content: &str) -> RenderResult;
+    fn render_verbatim(&self, context: &RenderContext, content: &str) -> RenderResult;
+
+    /// Renders the unmodified textual content of a file, line by line.
+    ///
+    /// This is used instead of `render_verbatim` when `verbatim_line_numbers` is turned on
+    /// in the configuration. The line at `lines[n]` is line `n + 1` of the file, and is
+    /// expected to be given the anchor `L{n + 1}`, so that `file.rs#L42` links to line 42.
+    ///
+    /// By default, the lines are joined and rendered by `render_verbatim`.
+    ///
+    /// # Arguments
+    /// * `context` - The context of the request to the specified file
+    /// * `lines`   - The lines of the file, without their line endings
+    ///
+    fn render_verbatim_lines(&self, context: &RenderContext, lines: &[&str]) -> RenderResult {
+        self.render_verbatim(context, &lines.join("\n"))
+    }
 
     /// Renders a file as a Markdown file.
     ///
     /// # Arguments
-    /// * `path_str` - The path to the specified file as an `str` slice
-    /// * `content`  - The content of the file, already rendered to HTML
+    /// * `context` - The context of the request to the specified file
+    /// * `content` - The content of the file, already rendered to HTML
+    ///
+    fn render_markdown(&self, context: &RenderContext, content: &str) -> RenderResult;
+
+    /// Renders the README of the root directory as the landing page.
+    ///
+    /// This is used instead of `render_dir` for the root directory when `root_readme` is turned on
+    /// in the configuration.
+    ///
+    /// By default, only the README is rendered by `render_markdown`.
+    ///
+    /// # Arguments
+    /// * `context` - The context of the request to the root directory
+    /// * `readme`  - The content of the README, already rendered to HTML
+    /// * `entries` - The entries in the root directory, which may be listed below the README
     ///
-    fn render_markdown(&self, path_str: &str, content: &str) -> RenderResult;
+    fn render_readme(&self, context: &RenderContext, readme: &str, entries: &[Entry])
+            -> RenderResult {
+        let _ = entries;
+        self.render_markdown(context, readme)
+    }
 
     /// Renders an error message
     ///
     /// # Arguments
-    /// * `path_str` - The path to the specified file as an `str` slice
-    /// * `code`     - HTTP status code for the error
-    /// * `message`  - An message describing the error
+    /// * `context` - The context of the request to the specified file
+    /// * `code`    - HTTP status code for the error
+    /// * `message` - An message describing the error
     ///
     fn render_error(
         &self,
-        path_str: &str,
+        context: &RenderContext,
         code: usize,
         message: &str
     ) -> RenderResult;
 }
 
+/// A renderer producing plain, lightly styled pages
+///
+/// This is enough to browse the served files without writing a renderer first. Listings show the
+/// size and the modification time of each entry, and every page links back to the directory
+/// above it. Names, paths, text and messages are escaped, while rendered Markdown is included as
+/// it is.
+///
+/// ```ignore
+/// let archivist = Archivist::summon(&config, Arc::new(DefaultRenderer::new()));
+/// ```
+///
+#[derive(Debug, Clone, Default)]
+pub struct DefaultRenderer;
+
+impl DefaultRenderer {
+    /// Builds the renderer
+    pub fn new() -> Self {
+        DefaultRenderer
+    }
+
+    // Wraps the body in a whole page titled with the path
+    fn page(&self, context: &RenderContext, body: &str) -> String {
+        let nonce = context.csp_nonce.as_ref()
+            .map(|n| format!(" nonce=\"{}\"", escape_html(n)))
+            .unwrap_or_default();
+        format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+             <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+             <title>/{0}</title><style{1}>{2}</style></head>\
+             <body><h1>/{0}</h1>{3}</body></html>\n",
+            escape_html(&context.path),
+            nonce,
+            DEFAULT_STYLE,
+            body
+        )
+    }
+
+    // Links to the pages before and after the page of the listing
+    fn page_nav(&self, page: &ListingPage) -> String {
+        let mut nav = String::from("<nav>");
+        if let Some(ref prev) = page.prev {
+            nav.push_str(&format!("<a href=\"{}\" rel=\"prev\">Previous</a> ", escape_html(prev)));
+        }
+        if let (Some(number), Some(count)) = (page.number, page.count) {
+            nav.push_str(&format!("Page {} of {} ", number, count));
+        }
+        if let Some(ref next) = page.next {
+            nav.push_str(&format!("<a href=\"{}\" rel=\"next\">Next</a>", escape_html(next)));
+        }
+        nav.push_str("</nav>");
+        nav
+    }
+
+    // The rows of the entries in a listing, indented by their depth in the tree
+    fn entry_rows(&self, tree: &[TreeEntry], depth: usize, rows: &mut String) {
+        for t in tree {
+            let e = &t.entry;
+            rows.push_str(&format!(
+                "<tr><td>{}<a href=\"{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>",
+                "&emsp;".repeat(depth),
+                escape_html(&e.href),
+                e.escaped_display_name(),
+                if e.is_dir { "/" } else { "" },
+                if e.is_dir { String::new() } else { e.human_size() },
+                escape_html(&e.modified)
+            ));
+            self.entry_rows(&t.children, depth + 1, rows);
+            // Entries left out of the tree are hinted at
+            if t.truncated {
+                rows.push_str(&format!(
+                    "<tr><td>{}&hellip;</td><td></td><td></td></tr>",
+                    "&emsp;".repeat(depth + 1)
+                ));
+            }
+        }
+    }
+}
+
+impl Renderer for DefaultRenderer {
+    fn render_dir(&self, context: &RenderContext, entries: &[Entry]) -> RenderResult {
+        let tree : Vec<TreeEntry> = entries.iter()
+            .map(|e| TreeEntry { entry: e.clone(), children: Vec::new(), truncated: false })
+            .collect();
+        self.render_dir_tree(context, &tree)
+    }
+
+    fn render_dir_tree(&self, context: &RenderContext, tree: &[TreeEntry]) -> RenderResult {
+        let mut body = String::from("<table><tr><th>Name</th><th>Size</th><th>Modified</th></tr>");
+        // The root has nothing above it
+        if !context.path.is_empty() {
+            body.push_str("<tr><td><a href=\"..\">../</a></td><td></td><td></td></tr>");
+        }
+        self.entry_rows(tree, 0, &mut body);
+        body.push_str("</table>");
+        if let Some(ref page) = context.page {
+            body.push_str(&self.page_nav(page));
+        }
+        Ok(self.page(context, &body))
+    }
+
+    fn render_verbatim(&self, context: &RenderContext, content: &str) -> RenderResult {
+        Ok(self.page(context, &format!(
+            "<nav><a href=\".\">Back</a></nav><pre>{}</pre>",
+            escape_html(content)
+        )))
+    }
+
+    fn render_verbatim_lines(&self, context: &RenderContext, lines: &[&str]) -> RenderResult {
+        let mut body = String::from("<nav><a href=\".\">Back</a></nav><pre>");
+        for (n, line) in lines.iter().enumerate() {
+            body.push_str(&format!(
+                "<span id=\"L{0}\"><a class=\"line\" href=\"#L{0}\">{0}</a>{1}</span>\n",
+                n + 1,
+                escape_html(line)
+            ));
+        }
+        body.push_str("</pre>");
+        Ok(self.page(context, &body))
+    }
+
+    fn render_markdown(&self, context: &RenderContext, content: &str) -> RenderResult {
+        Ok(self.page(context, &format!(
+            "<nav><a href=\".\">Back</a></nav><article>{}</article>",
+            content
+        )))
+    }
+
+    fn render_error(&self, context: &RenderContext, code: usize, message: &str)
+            -> RenderResult {
+        Ok(self.page(context, &format!(
+            "<nav><a href=\".\">Back</a></nav><h2>Error {}</h2><p>{}</p>",
+            code,
+            escape_html(message)
+        )))
+    }
+}
+
+// The style sheet of the pages of the default renderer
+const DEFAULT_STYLE: &str = "\
+    body { font-family: sans-serif; max-width: 60em; margin: 2em auto; padding: 0 1em; }\
+    table { border-collapse: collapse; width: 100%; }\
+    th, td { text-align: left; padding: 0.2em 1em 0.2em 0; }\
+    td:nth-child(2) { text-align: right; white-space: nowrap; }\
+    pre { overflow-x: auto; background: #f6f6f6; padding: 1em; }\
+    a.line { display: inline-block; width: 4em; color: #999; text-decoration: none; }";
+
+/// Escapes the characters with special meanings in HTML
+///
+/// The escaped text can be put both between tags and in quoted attribute values. Renderers
+/// should escape every name, path and text they include in their pages, as these come from the
+/// file system or from the request.
+///
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,191 @@
+// Helpers shared by the tests of the modules
+//
+// Archivists are tested through real connections, as iron requests cannot be built otherwise
+
+use iron::prelude::*;
+use iron::middleware::Handler;
+
+use std::fs;
+use std::io::prelude::*;
+use std::mem;
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::env;
+
+use entry::Entry;
+use listing::TreeEntry;
+use renderer::*;
+
+static DIRS: AtomicUsize = AtomicUsize::new(0);
+
+// A directory removed with everything in it when dropped
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let path = env::temp_dir().join(format!(
+            "iron-archivist-test-{}-{}",
+            process::id(),
+            DIRS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Writes the file at the path relative to the directory, creating its parents
+    pub fn file(&self, rel_path: &str, content: &str) -> PathBuf {
+        let path = self.path.join(rel_path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+// Renders everything as plain lines that are easy to look into
+pub struct TestRenderer;
+
+impl Renderer for TestRenderer {
+    fn render_dir(&self, context: &RenderContext, entries: &[Entry]) -> RenderResult {
+        let mut page = format!("dir /{}\n", context.path);
+        for e in entries {
+            page.push_str(&e.href);
+            page.push('\n');
+        }
+        Ok(page)
+    }
+
+    // Each entry is indented by its depth, and truncated directories are followed by `...'
+    fn render_dir_tree(&self, context: &RenderContext, tree: &[TreeEntry]) -> RenderResult {
+        fn rows(tree: &[TreeEntry], depth: usize, page: &mut String) {
+            for t in tree {
+                page.push_str(&format!("{}{}\n", "  ".repeat(depth), t.entry.href));
+                rows(&t.children, depth + 1, page);
+                if t.truncated {
+                    page.push_str(&format!("{}...\n", "  ".repeat(depth + 1)));
+                }
+            }
+        }
+        let mut page = format!("tree /{}\n", context.path);
+        rows(tree, 0, &mut page);
+        Ok(page)
+    }
+
+    // The path in the file system, if exposed, follows the path of the page
+    fn render_verbatim(&self, context: &RenderContext, content: &str) -> RenderResult {
+        match context.fs_path {
+            Some(ref fs_path) =>
+                Ok(format!("verbatim /{} {}\n{}", context.path, fs_path.display(), content)),
+            None => Ok(format!("verbatim /{}\n{}", context.path, content)),
+        }
+    }
+
+    fn render_markdown(&self, context: &RenderContext, content: &str) -> RenderResult {
+        Ok(format!("markdown /{}\n{}", context.path, content))
+    }
+
+    fn render_error(&self, _: &RenderContext, code: usize, message: &str) -> RenderResult {
+        Ok(format!("error {} {}", code, message))
+    }
+}
+
+// Starts serving with the handler on a port of its own
+//
+// The server is never stopped, as hyper cannot stop it
+pub fn serve<H: Handler>(handler: H) -> SocketAddr {
+    let mut iron = Iron::new(handler);
+    iron.threads = 2;
+    let listening = iron.http("127.0.0.1:0").unwrap();
+    let address = listening.socket;
+    mem::forget(listening);
+    address
+}
+
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    // The value of the header, compared ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|h| h.0.eq_ignore_ascii_case(name))
+            .map(|h| h.1.as_str())
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+// Sends the request, given up to the end of its headers, and reads the whole response
+pub fn request(address: SocketAddr, head: &str) -> TestResponse {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(head.as_bytes()).unwrap();
+    stream.write_all(b"Connection: close\r\n\r\n").unwrap();
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).unwrap();
+
+    let end = raw.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = str::from_utf8(&raw[..end]).unwrap();
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap()
+        .split(' ')
+        .nth(1).unwrap()
+        .parse().unwrap();
+    let headers : Vec<(String, String)> = lines
+        .map(|l| {
+            let mut parts = l.splitn(2, ':');
+            let name = parts.next().unwrap().trim().to_owned();
+            (name, parts.next().unwrap_or("").trim().to_owned())
+        })
+        .collect();
+    let mut body = raw[end + 4..].to_vec();
+    let chunked = headers.iter()
+        .any(|h| h.0.eq_ignore_ascii_case("Transfer-Encoding") && h.1.contains("chunked"));
+    if chunked {
+        body = dechunk(&body);
+    }
+    TestResponse {
+        status,
+        headers,
+        body,
+    }
+}
+
+// Sends a GET request for the path, which may have a query
+pub fn get(address: SocketAddr, path: &str) -> TestResponse {
+    request(address, &format!("GET {} HTTP/1.1\r\nHost: localhost\r\n", path))
+}
+
+fn dechunk(mut chunked: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = chunked.windows(2).position(|w| w == b"\r\n").unwrap();
+        let size = usize::from_str_radix(
+            str::from_utf8(&chunked[..line_end]).unwrap().trim(),
+            16
+        ).unwrap();
+        if size == 0 {
+            return body;
+        }
+        body.extend_from_slice(&chunked[line_end + 2..line_end + 2 + size]);
+        chunked = &chunked[line_end + 4 + size..];
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits how many requests each client can make in a window of time
+///
+/// Each client has a bucket of tokens that refills steadily over the window, and each request
+/// takes a token from it. Clients with empty buckets are turned away until a token is refilled.
+///
+pub struct Throttle {
+    capacity: f64,
+    window: Duration,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    buckets: HashMap<IpAddr, Bucket>,
+    // When the buckets of clients not seen for a while were last thrown away
+    swept_at: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Throttle {
+    /// Builds a throttle allowing each client a number of requests in a window of time
+    ///
+    /// At least one request is allowed, and the window is at least a millisecond long, so that the
+    /// refill rate is always a finite, positive number.
+    ///
+    /// # Arguments
+    /// * `limit`  - The number of requests allowed in the window
+    /// * `window` - The length of the window
+    ///
+    pub fn new(limit: u32, window: Duration) -> Throttle {
+        Throttle {
+            capacity: f64::from(limit.max(1)),
+            window: window.max(Duration::from_millis(1)),
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                swept_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a token from the bucket of the client
+    ///
+    /// Returns how long until the client can make another request if its bucket is empty.
+    ///
+    /// # Arguments
+    /// * `client` - The address of the client
+    ///
+    pub fn take(&self, client: IpAddr) -> Result<(), Duration> {
+        self.take_at(client, Instant::now())
+    }
+
+    // Takes a token from the bucket of the client as of the specified time
+    fn take_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        // Do not turn anyone away because of a panic elsewhere
+        let mut buckets = match self.buckets.lock() {
+            Ok(b) => b,
+            Err(_) => return Ok(()),
+        };
+        let rate = self.capacity / self.window.as_secs_f64();
+
+        // The buckets of clients not seen for a whole window are full again,
+        // so they can be thrown away without changing anything
+        if now.duration_since(buckets.swept_at) >= self.window {
+            let window = self.window;
+            buckets.buckets.retain(|_, b| now.duration_since(b.updated_at) < window);
+            buckets.swept_at = now;
+        }
+
+        let capacity = self.capacity;
+        let bucket = buckets.buckets.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{Duration, Instant};
+
+    use super::Throttle;
+
+    fn client(n: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, n))
+    }
+
+    // The waits are worked out in floating point, so they are compared to the millisecond
+    fn assert_wait(result: Result<(), Duration>, expected: Duration) {
+        let wait = result.unwrap_err();
+        assert!(wait.abs_diff(expected) < Duration::from_millis(1), "{:?} {:?}", wait, expected);
+    }
+
+    #[test]
+    fn buckets_refill_over_the_window() {
+        let throttle = Throttle::new(2, Duration::from_secs(10));
+        let start = Instant::now();
+        let at = |secs: f64| start + Duration::from_secs_f64(secs);
+
+        assert!(throttle.take_at(client(1), at(0.0)).is_ok());
+        assert!(throttle.take_at(client(1), at(0.0)).is_ok());
+        // A token is refilled every 5 seconds
+        assert_wait(throttle.take_at(client(1), at(0.0)), Duration::from_secs(5));
+        assert_wait(throttle.take_at(client(1), at(2.5)), Duration::from_millis(2500));
+        assert!(throttle.take_at(client(1), at(5.0)).is_ok());
+        assert_wait(throttle.take_at(client(1), at(5.0)), Duration::from_secs(5));
+
+        // Other clients have buckets of their own
+        assert!(throttle.take_at(client(2), at(5.0)).is_ok());
+
+        // Buckets never hold more than the limit
+        assert!(throttle.take_at(client(1), at(100.0)).is_ok());
+        assert!(throttle.take_at(client(1), at(100.0)).is_ok());
+        assert_wait(throttle.take_at(client(1), at(100.0)), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn zero_limits_are_raised() {
+        let start = Instant::now();
+        let throttle = Throttle::new(0, Duration::from_secs(60));
+        assert!(throttle.take_at(client(1), start).is_ok());
+        assert_wait(throttle.take_at(client(1), start), Duration::from_secs(60));
+
+        // The wait is finite even for an empty window
+        let throttle = Throttle::new(1, Duration::from_secs(0));
+        assert!(throttle.take_at(client(1), start).is_ok());
+        assert_wait(throttle.take_at(client(1), start), Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::fs;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use config::*;
+
+/// A servable file or directory found while walking a directory tree
+#[derive(Debug, Clone)]
+pub struct WalkedEntry {
+    /// The path to the entry in the file system
+    pub path: PathBuf,
+    /// The path to the entry, relative to the directory being walked
+    pub rel_path: PathBuf,
+    /// How the entry would be accessed
+    pub method: AccessMethod,
+    /// The metadata of the entry
+    pub metadata: Metadata,
+    /// Whether or not the entry is a symbolic link
+    pub is_symlink: bool,
+    /// Whether or not the entries in the directory were left out, as the walk went too deep or
+    /// found too many entries
+    pub truncated: bool,
+}
+
+/// The result of walking a directory tree
+#[derive(Debug, Clone, Default)]
+pub struct Walk {
+    /// The servable entries, each directory followed by its own entries
+    pub entries: Vec<WalkedEntry>,
+    /// Whether or not some directories were not walked because they were too deep, or because
+    /// too many entries were found
+    ///
+    /// The entries of such directories are missing, so the walk does not cover the whole tree.
+    pub truncated: bool,
+    /// Whether or not the walk stopped before finding all of the entries in the directory itself,
+    /// as it had found as many entries as allowed
+    pub stopped_early: bool,
+    /// The number of entries and subdirectories left out because they could not be read
+    pub skipped: usize,
+}
+
+/// How far a walk goes
+#[derive(Debug, Clone, Copy)]
+pub struct WalkLimit {
+    /// The number of levels of subdirectories walked below the directory
+    pub depth: usize,
+    /// The number of entries after which the walk stops
+    pub entries: usize,
+    /// Whether or not to walk directories behind symbolic links
+    pub follow_symlinks: bool,
+}
+
+impl WalkLimit {
+    /// Walks as deep as `max_depth` in the configuration, without following symbolic links
+    pub fn of(config: &Config) -> WalkLimit {
+        WalkLimit {
+            depth: config.max_depth,
+            entries: usize::MAX,
+            follow_symlinks: false,
+        }
+    }
+}
+
+/// Walks the servable entries in a directory tree, as far as the limit allows
+///
+/// Each directory is only walked once, so that symbolic links pointing back up the tree cannot
+/// make the walk go on forever. Entries and subdirectories that cannot be read, such as those
+/// removed in the meantime, are left out and counted in `skipped`.
+///
+/// # Arguments
+/// * `dir`    - The path to the directory in the file system
+/// * `config` - The configuration deciding which entries are servable
+/// * `limit`  - How far to walk
+///
+/// # Error
+/// Returns an error if the directory cannot be read.
+///
+pub fn walk_with_limit(dir: &Path, config: &Config, limit: WalkLimit) -> io::Result<Walk> {
+    let mut walk = Walk::default();
+    let mut visited = HashSet::new();
+    visited.insert(dir.canonicalize()?);
+    walk.stopped_early = walk_dir(dir, Path::new(""), 0, config, limit, &mut visited, &mut walk)?;
+    Ok(walk)
+}
+
+// Walks the entries in the directory, and those below it
+// Returns whether or not the walk stopped before all of them were found
+fn walk_dir(
+    dir: &Path,
+    rel_dir: &Path,
+    depth: usize,
+    config: &Config,
+    limit: WalkLimit,
+    visited: &mut HashSet<PathBuf>,
+    walk: &mut Walk,
+) -> io::Result<bool> {
+    for e in fs::read_dir(dir)? {
+        if walk.entries.len() >= limit.entries {
+            walk.truncated = true;
+            return Ok(true);
+        }
+        let e = match e {
+            Ok(e) => e,
+            Err(_) => {
+                walk.skipped += 1;
+                continue;
+            },
+        };
+        let path = e.path();
+        let method = match config.method_for(&path) {
+            Ok(Some(m)) => m,
+            _ => continue,
+        };
+        // Files still being written are left out
+        if config.time_until_written(&path).is_some() {
+            continue;
+        }
+        let (metadata, file_type) = match (path.metadata(), e.file_type()) {
+            (Ok(md), Ok(ft)) => (md, ft),
+            _ => {
+                walk.skipped += 1;
+                continue;
+            },
+        };
+        let is_symlink = file_type.is_symlink();
+        let rel_path = rel_dir.join(e.file_name());
+        let walked = WalkedEntry {
+            path: path.clone(),
+            rel_path: rel_path.clone(),
+            method,
+            metadata,
+            is_symlink,
+            truncated: false,
+        };
+
+        if !method.is_dir() || is_symlink && !limit.follow_symlinks {
+            walk.entries.push(walked);
+            continue;
+        }
+        if depth >= limit.depth {
+            walk.truncated = true;
+            walk.entries.push(WalkedEntry { truncated: true, ..walked });
+            continue;
+        }
+        walk.entries.push(walked);
+        let index = walk.entries.len() - 1;
+        // Directories already walked are skipped
+        let canonical = match path.canonicalize() {
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+        if !visited.insert(canonical) {
+            continue;
+        }
+        match walk_dir(&path, &rel_path, depth + 1, config, limit, visited, walk) {
+            Ok(true) => walk.entries[index].truncated = true,
+            Ok(false) => (),
+            Err(_) => walk.skipped += 1,
+        }
+    }
+    Ok(false)
+}